@@ -13,14 +13,18 @@ pub fn main() -> Result<(), Box<dyn Error>> {
     let pci_addr = match args.next() {
         Some(arg) => arg,
         None => {
-            eprintln!("Usage: cargo run --example init <pci bus id>");
+            eprintln!("Usage: cargo run --example fill_namespace <pci bus id> [zero]");
             process::exit(1);
         }
     };
 
     let mut nvme = vroom::init(&pci_addr)?;
 
-    fill_ns(&mut nvme);
+    if args.next().as_deref() == Some("zero") {
+        zero_ns(&mut nvme)?;
+    } else {
+        fill_ns(&mut nvme);
+    }
 
     Ok(())
 }
@@ -28,11 +32,19 @@ pub fn main() -> Result<(), Box<dyn Error>> {
 fn fill_ns(nvme: &mut NvmeDevice) {
     println!("filling namespace");
     let buffer: Dma<u8> = nvme.allocate(PAGESIZE_2MIB).unwrap();
-    let max_lba = nvme.namespaces.get(&1).unwrap().blocks - buffer.size as u64 / 512 - 1;
-    let blocks = buffer.size as u64 / 512;
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let blocks = buffer.size as u64 / ns.block_size;
+    let max_lba = ns.blocks - blocks - 1;
     let mut lba = 0;
-    while lba < max_lba - 512 {
-        nvme.write(&buffer, lba).unwrap();
+    while lba < max_lba - blocks {
+        nvme.write(&ns, &buffer, lba).unwrap();
         lba += blocks;
     }
 }
+
+fn zero_ns(nvme: &mut NvmeDevice) -> Result<(), Box<dyn Error>> {
+    println!("zeroing namespace");
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    nvme.write_zeroes(&ns, 0, ns.blocks, false)?;
+    Ok(())
+}
@@ -14,10 +14,11 @@ pub fn main() -> Result<(), Box<dyn Error>> {
     };
 
     let mut nvme = vroom::init(&pci_addr)?;
-    nvme.write_copied("hello world".as_bytes(), 0)?;
+    let ns = nvme.namespace(1)?;
+    nvme.write_copied(&ns, "hello world".as_bytes(), 0)?;
 
     let mut dest = [0u8; 12];
-    nvme.read_copied(&mut dest, 0)?;
+    nvme.read_copied(&ns, &mut dest, 0)?;
 
     println!("{}", std::str::from_utf8(&dest)?);
 
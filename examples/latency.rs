@@ -9,7 +9,7 @@ use std::{env, process, thread, vec};
 use vroom::memory::*;
 use vroom::Mapping;
 
-use vroom::{NvmeDevice, QUEUE_LENGTH};
+use vroom::NvmeDevice;
 
 pub fn main() -> Result<(), Box<dyn Error>> {
     let mut args = env::args();
@@ -69,8 +69,10 @@ fn qd_n_multithread_latency_nanos(
     random: bool,
     write: bool,
 ) -> Result<(NvmeDevice, Vec<u128>), Box<dyn Error>> {
-    let blocks = 8;
-    let ns_blocks = nvme.namespaces.get(&1).unwrap().blocks / blocks;
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    // 4 KiB per I/O
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+    let ns_blocks = ns.blocks / blocks;
     let nvme = Arc::new(Mutex::new(nvme));
     let mut threads = Vec::new();
 
@@ -83,7 +85,7 @@ fn qd_n_multithread_latency_nanos(
 
         let handle = thread::spawn(move || {
             let mut rng = rand::thread_rng();
-            let bytes = 512 * blocks as usize;
+            let bytes = (blocks * ns.block_size) as usize;
 
             let mut buffer = nvme.lock().unwrap().allocate::<u8>(PAGESIZE_2MIB).unwrap();
 
@@ -110,11 +112,14 @@ fn qd_n_multithread_latency_nanos(
                 };
                 let before = Instant::now();
 
-                qpair.submit_io(
-                    &buffer.slice((outstanding_ops * bytes)..(outstanding_ops + 1) * bytes),
-                    lba * blocks,
-                    write,
-                );
+                qpair
+                    .submit_io(
+                        &ns,
+                        &buffer.slice((outstanding_ops * bytes)..(outstanding_ops + 1) * bytes),
+                        lba * blocks,
+                        write,
+                    )
+                    .unwrap();
 
                 outstanding_ops += 1;
 
@@ -173,8 +178,10 @@ fn qd_n_multithread(
     random: bool,
     write: bool,
 ) -> Result<(NvmeDevice, Vec<u128>), Box<dyn Error>> {
-    let blocks = 8;
-    let ns_blocks = nvme.namespaces.get(&1).unwrap().blocks / blocks;
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    // 4 KiB per I/O
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+    let ns_blocks = ns.blocks / blocks;
 
     let nvme = Arc::new(Mutex::new(nvme));
     let mut threads = Vec::new();
@@ -189,7 +196,7 @@ fn qd_n_multithread(
 
         let handle = thread::spawn(move || -> (u64, f64) {
             let mut rng = rand::thread_rng();
-            let bytes = 512 * blocks as usize; // 4kib
+            let bytes = (blocks * ns.block_size) as usize; // 4kib
             let mut total = std::time::Duration::ZERO;
 
             let mut buffer = nvme.lock().unwrap().allocate(PAGESIZE_4KIB).unwrap();
@@ -197,7 +204,7 @@ fn qd_n_multithread(
             let mut qpair = nvme
                 .lock()
                 .unwrap()
-                .create_io_queue_pair(QUEUE_LENGTH)
+                .create_io_queue_pair(queue_depth + 1)
                 .unwrap();
 
             let buffer_size = queue_depth * bytes;
@@ -217,15 +224,18 @@ fn qd_n_multithread(
                     total_io_ops += 1;
                 }
                 if outstanding_ops == queue_depth {
-                    qpair.complete_io(1);
+                    qpair.complete_io(1).unwrap();
                     outstanding_ops -= 1;
                     total_io_ops += 1;
                 }
-                qpair.submit_io(
-                    &buffer.slice((outstanding_ops * bytes)..(outstanding_ops + 1) * bytes),
-                    lba * blocks,
-                    write,
-                );
+                qpair
+                    .submit_io(
+                        &ns,
+                        &buffer.slice((outstanding_ops * bytes)..(outstanding_ops + 1) * bytes),
+                        lba * blocks,
+                        write,
+                    )
+                    .unwrap();
                 latencies.lock().unwrap().push(before.elapsed().as_nanos());
                 total += before.elapsed();
                 outstanding_ops += 1;
@@ -233,12 +243,12 @@ fn qd_n_multithread(
 
             if outstanding_ops != 0 {
                 let before = Instant::now();
-                qpair.complete_io(outstanding_ops);
+                qpair.complete_io(outstanding_ops).unwrap();
                 latencies.lock().unwrap().push(before.elapsed().as_nanos());
                 total += before.elapsed();
             }
             total_io_ops += outstanding_ops as u64;
-            assert!(qpair.sub_queue.is_empty());
+            assert!(qpair.sub_queue().is_empty());
             nvme.lock().unwrap().delete_io_queue_pair(&qpair).unwrap();
 
             (total_io_ops, total_io_ops as f64 / total.as_secs_f64())
@@ -282,9 +292,11 @@ fn qd_1_singlethread_latency(
 ) -> Result<(NvmeDevice, Vec<u128>), Box<dyn Error>> {
     let mut buffer = nvme.allocate(PAGESIZE_2MIB)?;
 
-    let blocks = 8;
-    let bytes = 512 * blocks;
-    let ns_blocks = nvme.namespaces.get(&1).unwrap().blocks / blocks - 1;
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    // 4 KiB per I/O
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+    let bytes = blocks * ns.block_size;
+    let ns_blocks = ns.blocks / blocks - 1;
 
     let mut rng = rand::thread_rng();
 
@@ -305,9 +317,9 @@ fn qd_1_singlethread_latency(
 
         let before = Instant::now();
         if write {
-            nvme.write(&buffer.slice(0..bytes as usize), lba * blocks)?;
+            nvme.write(&ns, &buffer.slice(0..bytes as usize), lba * blocks)?;
         } else {
-            nvme.read(&buffer.slice(0..bytes as usize), lba * blocks)?;
+            nvme.read(&ns, &buffer.slice(0..bytes as usize), lba * blocks)?;
         }
         let elapsed = before.elapsed();
         latencies.push(elapsed.as_nanos());
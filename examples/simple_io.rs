@@ -21,6 +21,7 @@ pub fn main() -> Result<(), Box<dyn Error>> {
 
     // Initialize NVMe Driver
     let mut nvme = vroom::init_with_page_size(&pci_addr, Pagesize::Page4K)?;
+    let ns = nvme.namespace(1)?;
 
     // Add Test bytes and copy to DMA
     let bytes: &[u8] = "hello world! vroom test bytes".as_bytes();
@@ -28,13 +29,13 @@ pub fn main() -> Result<(), Box<dyn Error>> {
     buffer[..bytes.len()].copy_from_slice(bytes);
 
     // Write the bytes to the NVMe memory
-    nvme.write(&buffer, lba)?;
+    nvme.write(&ns, &buffer, lba)?;
 
     // Empty the buffer
     buffer[..bytes.len()].fill_with(Default::default);
 
     // Read the written bytes
-    nvme.read(&buffer, lba)?;
+    nvme.read(&ns, &buffer, lba)?;
     let read_buf = &buffer[0..bytes.len()];
     println!("read bytes: {:?}", read_buf);
     println!("read string: {}", str::from_utf8(read_buf).unwrap());
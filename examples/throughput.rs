@@ -7,7 +7,7 @@ use std::{env, process, thread};
 use vroom::memory::*;
 use vroom::vfio::Vfio;
 use vroom::Mapping;
-use vroom::{NvmeDevice, QUEUE_LENGTH};
+use vroom::NvmeDevice;
 
 pub fn main() -> Result<(), Box<dyn Error>> {
     let mut args = env::args();
@@ -90,8 +90,10 @@ fn qd_n_multithread(
     random: bool,
     write: bool,
 ) -> Result<NvmeDevice, Box<dyn Error>> {
-    let blocks = 8;
-    let ns_blocks = nvme.namespaces.get(&1).unwrap().blocks / blocks;
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    // 4 KiB per I/O
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+    let ns_blocks = ns.blocks / blocks;
 
     let nvme = Arc::new(Mutex::new(nvme));
     let mut threads = Vec::new();
@@ -101,7 +103,7 @@ fn qd_n_multithread(
 
         let handle = thread::spawn(move || -> (u64, f64) {
             let mut rng = rand::thread_rng();
-            let bytes = 512 * blocks as usize; // 4kib
+            let bytes = (blocks * ns.block_size) as usize; // 4kib
             let mut total = std::time::Duration::ZERO;
 
             let mut buffer = nvme
@@ -112,7 +114,7 @@ fn qd_n_multithread(
             let mut qpair = nvme
                 .lock()
                 .unwrap()
-                .create_io_queue_pair(QUEUE_LENGTH)
+                .create_io_queue_pair(queue_depth + 1)
                 .unwrap();
 
             let buffer_size = queue_depth * bytes;
@@ -138,26 +140,29 @@ fn qd_n_multithread(
                     total_io_ops += 1;
                 }
                 if outstanding_ops == queue_depth {
-                    qpair.complete_io(1);
+                    qpair.complete_io(1).unwrap();
                     outstanding_ops -= 1;
                     total_io_ops += 1;
                 }
-                qpair.submit_io(
-                    &buffer.slice((outstanding_ops * bytes)..(outstanding_ops + 1) * bytes),
-                    lba * blocks,
-                    write,
-                );
+                qpair
+                    .submit_io(
+                        &ns,
+                        &buffer.slice((outstanding_ops * bytes)..(outstanding_ops + 1) * bytes),
+                        lba * blocks,
+                        write,
+                    )
+                    .unwrap();
                 total += before.elapsed();
                 outstanding_ops += 1;
             }
 
             if outstanding_ops != 0 {
                 let before = Instant::now();
-                qpair.complete_io(outstanding_ops);
+                qpair.complete_io(outstanding_ops).unwrap();
                 total += before.elapsed();
             }
             total_io_ops += outstanding_ops as u64;
-            assert!(qpair.sub_queue.is_empty());
+            assert!(qpair.sub_queue().is_empty());
             nvme.lock().unwrap().delete_io_queue_pair(&qpair).unwrap();
 
             (total_io_ops, total_io_ops as f64 / total.as_secs_f64())
@@ -193,9 +198,11 @@ fn qd_1_singlethread(
     random: bool,
     duration: Duration,
 ) -> Result<NvmeDevice, Box<dyn Error>> {
-    let blocks = 8;
-    let bytes = 512 * blocks;
-    let ns_blocks = nvme.namespaces.get(&1).unwrap().blocks / blocks - 1; // - blocks - 1;
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    // 4 KiB per I/O
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+    let bytes = blocks * ns.block_size;
+    let ns_blocks = ns.blocks / blocks - 1; // - blocks - 1;
 
     let mut rng = thread_rng();
     let mut buffer = nvme.allocate(PAGESIZE_4KIB)?;
@@ -216,9 +223,9 @@ fn qd_1_singlethread(
 
         let before = Instant::now();
         if write {
-            nvme.write(&buffer.slice(0..bytes as usize), lba * blocks)?;
+            nvme.write(&ns, &buffer.slice(0..bytes as usize), lba * blocks)?;
         } else {
-            nvme.read(&buffer.slice(0..bytes as usize), lba * blocks)?;
+            nvme.read(&ns, &buffer.slice(0..bytes as usize), lba * blocks)?;
         }
         let elapsed = before.elapsed();
         total += elapsed;
@@ -0,0 +1,236 @@
+use crate::memory::Dma;
+use crate::nvme::{NvmeCompletedRequest, NvmeDevice, NvmeNamespace, NvmeQueuePair, NvmeRequest};
+use crate::status::NvmeStatus;
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::future::Future;
+use std::hint::spin_loop;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Queue pair driven by futures, usable with any executor.
+///
+/// The futures only wake up once their request completed, which a poller has to notice:
+/// either `poll` called from the executor's loop or a thread, or with the `tokio` feature `run_poller`,
+/// which sleeps on the completion fd of the queue pair.
+#[derive(Clone)]
+pub struct AsyncQueuePair {
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    qpair: NvmeQueuePair,
+    // futures are identified by tickets, as request handles are reused once a request completed
+    next_ticket: u64,
+    submitted: HashMap<NvmeRequest, u64>,
+    finished: HashMap<u64, NvmeStatus>,
+    wakers: HashMap<u64, Waker>,
+    // futures waiting for the queue to have room for their request
+    blocked: Vec<Waker>,
+}
+
+impl Shared {
+    /// Collects all finished requests and wakes their futures
+    fn drive(&mut self) {
+        while let Some(completed) = self.qpair.quick_poll() {
+            self.finish(completed);
+        }
+    }
+
+    fn finish(&mut self, completed: NvmeCompletedRequest) {
+        let Some(ticket) = self.submitted.remove(&completed.request) else {
+            return;
+        };
+        self.finished.insert(ticket, completed.status);
+        if let Some(waker) = self.wakers.remove(&ticket) {
+            waker.wake();
+        }
+        for waker in self.blocked.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl AsyncQueuePair {
+    #[must_use]
+    pub fn new(qpair: NvmeQueuePair) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                qpair,
+                next_ticket: 0,
+                submitted: HashMap::new(),
+                finished: HashMap::new(),
+                wakers: HashMap::new(),
+                blocked: Vec::new(),
+            })),
+        }
+    }
+
+    /// Reads `dest.size` bytes starting at `lba` of namespace `ns` into `dest`
+    #[must_use]
+    pub fn read<'a>(&self, ns: &NvmeNamespace, lba: u64, dest: &'a mut Dma<u8>) -> IoFuture<'a> {
+        self.io(*ns, lba, dest, false)
+    }
+
+    /// Writes `data` to namespace `ns`, starting at `lba`
+    #[must_use]
+    pub fn write<'a>(&self, ns: &NvmeNamespace, lba: u64, data: &'a Dma<u8>) -> IoFuture<'a> {
+        self.io(*ns, lba, data, true)
+    }
+
+    fn io<'a>(&self, ns: NvmeNamespace, lba: u64, data: &'a Dma<u8>, write: bool) -> IoFuture<'a> {
+        IoFuture {
+            shared: Arc::clone(&self.shared),
+            data,
+            ns,
+            lba,
+            write,
+            ticket: None,
+        }
+    }
+
+    /// Returns the queue pair, e.g. to delete it, if this is the last handle to it
+    #[must_use]
+    pub fn into_inner(self) -> Option<NvmeQueuePair> {
+        Arc::into_inner(self.shared).map(|shared| {
+            shared
+                .into_inner()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .qpair
+        })
+    }
+
+    /// Aborts the commands of the queue pair outstanding for longer than its command timeout,
+    /// which completes them, e.g. to release futures blocking in their drop.
+    /// # Errors
+    /// Returns an error if an Abort command failed
+    pub fn abort_expired(&self, nvme: &mut NvmeDevice) -> Result<Vec<NvmeRequest>> {
+        nvme.abort_expired(&mut lock(&self.shared).qpair)
+    }
+
+    /// Collects the requests which completed since the last poll and wakes their futures
+    pub fn poll(&self) {
+        lock(&self.shared).drive();
+    }
+
+    /// Drives the completions of the queue pair, sleeping on its completion fd in between.
+    /// Runs until it is dropped or an error occurred.
+    /// # Errors
+    /// Returns an error if the queue pair has no completion fd or waiting on it failed
+    #[cfg(feature = "tokio")]
+    pub async fn run_poller(&self) -> Result<()> {
+        use tokio::io::unix::AsyncFd;
+        use tokio::io::Interest;
+
+        let fd = lock(&self.shared).qpair.completion_fd().ok_or(
+            "queue pair has no completion fd, enable interrupts or a poll timer before creating it",
+        )?;
+        let fd = AsyncFd::with_interest(fd, Interest::READABLE)?;
+
+        loop {
+            fd.readable().await?.clear_ready();
+            let mut shared = lock(&self.shared);
+            for completed in shared.qpair.poll_completions()? {
+                shared.finish(completed);
+            }
+        }
+    }
+}
+
+// interval in which dropped futures check for the completion of their expired command
+const EXPIRED_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Read or write submitted to an `AsyncQueuePair`, resolves once the request completed.
+///
+/// The request is submitted on the first poll. Dropping the future after that blocks until the request completed,
+/// as the controller may still access the buffer. Past the command timeout of the queue pair it stops spinning and
+/// only checks now and then, until `AsyncQueuePair::abort_expired` aborted the command.
+pub struct IoFuture<'a> {
+    shared: Arc<Mutex<Shared>>,
+    data: &'a Dma<u8>,
+    ns: NvmeNamespace,
+    lba: u64,
+    write: bool,
+    ticket: Option<u64>,
+}
+
+impl Future for IoFuture<'_> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut shared = lock(&this.shared);
+        shared.drive();
+
+        let ticket = if let Some(ticket) = this.ticket {
+            ticket
+        } else {
+            if let Err(e) = shared.qpair.check_io(&this.ns, this.data, this.lba) {
+                return Poll::Ready(Err(e));
+            }
+            let sq_id = shared.qpair.id;
+            let Some(request) = shared
+                .qpair
+                .submit_namespace_io(sq_id, &this.ns, this.data, this.lba, this.write)
+            else {
+                if shared.qpair.outstanding() == 0 {
+                    return Poll::Ready(Err("request does not fit into the queue".into()));
+                }
+                shared.blocked.push(cx.waker().clone());
+                return Poll::Pending;
+            };
+            let ticket = shared.next_ticket;
+            shared.next_ticket += 1;
+            shared.submitted.insert(request, ticket);
+            this.ticket = Some(ticket);
+            ticket
+        };
+
+        if let Some(status) = shared.finished.remove(&ticket) {
+            this.ticket = None;
+            return Poll::Ready(if status.is_success() {
+                Ok(())
+            } else {
+                Err(Error::Nvme(status))
+            });
+        }
+
+        shared.wakers.insert(ticket, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for IoFuture<'_> {
+    fn drop(&mut self) {
+        let Some(ticket) = self.ticket else {
+            return;
+        };
+        let deadline = lock(&self.shared)
+            .qpair
+            .command_timeout
+            .map(|timeout| Instant::now() + timeout);
+        loop {
+            let mut shared = lock(&self.shared);
+            shared.wakers.remove(&ticket);
+            if shared.finished.remove(&ticket).is_some() {
+                return;
+            }
+            shared.drive();
+            drop(shared);
+            // the buffer may only be released once the command completed, aborted if it hangs
+            if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                std::thread::sleep(EXPIRED_POLL_INTERVAL);
+            } else {
+                spin_loop();
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use crate::mapping::Mapping;
+use crate::memory::Dma;
+use crate::{Error, Result, PAGESIZE_4KIB};
+use std::sync::Mutex;
+
+// CMBSZ capability bits
+const CMBSZ_SQS: u32 = 1 << 0;
+const CMBSZ_CQS: u32 = 1 << 1;
+const CMBSZ_RDS: u32 = 1 << 3;
+const CMBSZ_WDS: u32 = 1 << 4;
+
+/// Controller Memory Buffer, memory of the controller mapped through one of its BARs.
+///
+/// Regions are handed out in 4 KiB granules, `Dma::phys` of a region is the address the controller accesses it at.
+/// Through `Mapping` the buffer serves as allocation source for data buffers, so reads and writes move data
+/// between the media and controller memory without touching host memory.
+pub struct ControllerMemoryBuffer {
+    virt: *mut u8,
+    addr: u64,
+    size: usize,
+    cmbsz: u32,
+    // free regions as offset and length, sorted by offset
+    free: Mutex<Vec<(usize, usize)>>,
+}
+
+unsafe impl Send for ControllerMemoryBuffer {}
+
+unsafe impl Sync for ControllerMemoryBuffer {}
+
+impl ControllerMemoryBuffer {
+    /// `virt` is the mapping of the buffer, `addr` the address the controller recognizes it at and `cmbsz` the CMBSZ register
+    pub(crate) fn new(virt: *mut u8, addr: u64, size: usize, cmbsz: u32) -> Self {
+        // the start of the mapping may not be page aligned within the controller address space
+        let skip = (addr as usize).next_multiple_of(PAGESIZE_4KIB) - addr as usize;
+        let usable = size.saturating_sub(skip) & !(PAGESIZE_4KIB - 1);
+        Self {
+            virt,
+            addr,
+            size,
+            cmbsz,
+            free: Mutex::new(if usable == 0 {
+                Vec::new()
+            } else {
+                vec![(skip, usable)]
+            }),
+        }
+    }
+
+    /// Size of the buffer in bytes
+    #[must_use]
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the controller supports submission queues in the buffer (CMBSZ.SQS)
+    #[must_use]
+    pub const fn supports_submission_queues(&self) -> bool {
+        self.cmbsz & CMBSZ_SQS != 0
+    }
+
+    /// Whether the controller supports completion queues in the buffer (CMBSZ.CQS)
+    #[must_use]
+    pub const fn supports_completion_queues(&self) -> bool {
+        self.cmbsz & CMBSZ_CQS != 0
+    }
+
+    /// Whether the controller supports data buffers of reads in the buffer (CMBSZ.RDS)
+    #[must_use]
+    pub const fn supports_read_data(&self) -> bool {
+        self.cmbsz & CMBSZ_RDS != 0
+    }
+
+    /// Whether the controller supports data buffers of writes in the buffer (CMBSZ.WDS)
+    #[must_use]
+    pub const fn supports_write_data(&self) -> bool {
+        self.cmbsz & CMBSZ_WDS != 0
+    }
+
+    /// Carves a region of at least `size` bytes out of the buffer, `None` if there is no room left
+    pub(crate) fn allocate_region<T>(&self, size: usize) -> Option<Dma<T>> {
+        let size = size.max(1).next_multiple_of(PAGESIZE_4KIB);
+        let mut free = self
+            .free
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        let i = free.iter().position(|&(_, len)| len >= size)?;
+        let (offset, len) = free[i];
+        if len == size {
+            free.remove(i);
+        } else {
+            free[i] = (offset + size, len - size);
+        }
+        drop(free);
+
+        Some(Dma {
+            virt: unsafe { self.virt.add(offset) }.cast::<T>(),
+            phys: self.addr as usize + offset,
+            size,
+        })
+    }
+
+    /// Whether `dma` was allocated from this buffer
+    pub(crate) fn contains<T>(&self, dma: &Dma<T>) -> bool {
+        let start = self.virt as usize;
+        (start..start + self.size).contains(&(dma.virt as usize))
+    }
+
+    /// Returns the region of `dma` to the buffer, `false` if it wasn't allocated from it
+    pub(crate) fn release_region<T>(&self, dma: &Dma<T>) -> bool {
+        if !self.contains(dma) {
+            return false;
+        }
+        let offset = dma.virt as usize - self.virt as usize;
+        let mut free = self
+            .free
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+        let i = free.partition_point(|&(start, _)| start < offset);
+        free.insert(i, (offset, dma.size));
+        // merge with the following and the preceding free region
+        if i + 1 < free.len() && offset + dma.size == free[i + 1].0 {
+            free[i].1 += free[i + 1].1;
+            free.remove(i + 1);
+        }
+        if i > 0 && free[i - 1].0 + free[i - 1].1 == offset {
+            free[i - 1].1 += free[i].1;
+            free.remove(i);
+        }
+        drop(free);
+        true
+    }
+}
+
+impl Mapping for ControllerMemoryBuffer {
+    /// Allocates a data buffer of `size` bytes in controller memory
+    fn allocate<T>(&self, size: usize) -> Result<Dma<T>> {
+        if !self.supports_read_data() && !self.supports_write_data() {
+            return Err(Error::Vroom(
+                "controller memory buffer does not support data buffers".to_string(),
+            ));
+        }
+        self.allocate_region(size).ok_or_else(|| {
+            Error::Vroom(format!(
+                "no room for {size} bytes left in the controller memory buffer"
+            ))
+        })
+    }
+
+    fn deallocate<T>(&self, dma: &Dma<T>) -> Result<()> {
+        if self.release_region(dma) {
+            Ok(())
+        } else {
+            Err(Error::Vroom(
+                "buffer was not allocated from the controller memory buffer".to_string(),
+            ))
+        }
+    }
+
+    fn map_resource(&self) -> Result<(*mut u8, usize)> {
+        Ok((self.virt, self.size))
+    }
+}
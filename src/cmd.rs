@@ -1,3 +1,7 @@
+use std::mem;
+
+use crate::protection::{PiFormat, Protection};
+
 /// `NVMe` Spec 4.2
 /// Submission queue entry
 #[derive(Clone, Copy, Debug, Default)]
@@ -67,8 +71,7 @@ impl NvmeCommand {
             d_ptr: [ptr as u64, 0],
             cdw10: ((size as u32) << 16) | (q_id as u32),
             cdw11: ((cq_id as u32) << 16) | 1, /* Physically Contiguous */
-            //TODO: QPRIO
-            cdw12: 0, //TODO: NVMSETID
+            cdw12: 0,                          //TODO: NVMSETID
             cdw13: 0,
             cdw14: 0,
             cdw15: 0,
@@ -93,6 +96,16 @@ impl NvmeCommand {
         }
     }
 
+    /// Aborts the command `cid` submitted to submission queue `sq_id`
+    pub fn abort(c_id: u16, sq_id: u16, cid: u16) -> Self {
+        Self {
+            opcode: 8,
+            c_id,
+            cdw10: (u32::from(cid) << 16) | u32::from(sq_id),
+            ..Default::default()
+        }
+    }
+
     pub const fn identify_namespace(c_id: u16, ptr: usize, ns_id: u32) -> Self {
         Self {
             opcode: 6,
@@ -129,6 +142,22 @@ impl NvmeCommand {
         }
     }
 
+    /// Identify the I/O command set specific controller data of the NVM command set (CNS 06h)
+    pub const fn identify_controller_nvm(c_id: u16, ptr: usize) -> Self {
+        Self {
+            cdw10: 6,
+            ..Self::identify_controller(c_id, ptr)
+        }
+    }
+
+    /// Identify the I/O command set specific namespace data of the NVM command set (CNS 05h)
+    pub const fn identify_namespace_nvm(c_id: u16, ptr: usize, ns_id: u32) -> Self {
+        Self {
+            cdw10: 5,
+            ..Self::identify_namespace(c_id, ptr, ns_id)
+        }
+    }
+
     pub const fn identify_namespace_list(c_id: u16, ptr: usize, base: u32) -> Self {
         Self {
             opcode: 6,
@@ -156,6 +185,17 @@ impl NvmeCommand {
         }
     }
 
+    pub fn set_features(c_id: u16, ptr: usize, fid: u8, cdw11: u32) -> Self {
+        Self {
+            opcode: 9,
+            c_id,
+            d_ptr: [ptr as u64, 0],
+            cdw10: u32::from(fid),
+            cdw11,
+            ..Default::default()
+        }
+    }
+
     pub const fn io_read(
         c_id: u16,
         ns_id: u32,
@@ -206,6 +246,100 @@ impl NvmeCommand {
         }
     }
 
+    pub const fn flush(c_id: u16, ns_id: u32) -> Self {
+        Self {
+            opcode: 0,
+            flags: 0,
+            c_id,
+            ns_id,
+            _rsvd: 0,
+            md_ptr: 0,
+            d_ptr: [0, 0],
+            cdw10: 0,
+            cdw11: 0,
+            cdw12: 0,
+            cdw13: 0,
+            cdw14: 0,
+            cdw15: 0,
+        }
+    }
+
+    pub const fn io_compare(
+        c_id: u16,
+        ns_id: u32,
+        lba: u64,
+        blocks_1: u16,
+        ptr0: u64,
+        ptr1: u64,
+    ) -> Self {
+        Self {
+            opcode: 5,
+            ..Self::io_read(c_id, ns_id, lba, blocks_1, ptr0, ptr1)
+        }
+    }
+
+    /// Verify of `blocks_1` + 1 blocks starting at `lba`, checking their integrity without transferring data
+    pub const fn verify(c_id: u16, ns_id: u32, lba: u64, blocks_1: u16) -> Self {
+        Self {
+            opcode: 0x0C,
+            ..Self::io_read(c_id, ns_id, lba, blocks_1, 0, 0)
+        }
+    }
+
+    /// Write Uncorrectable of `blocks_1` + 1 blocks starting at `lba`, reads of them fail until they are written again
+    pub const fn write_uncorrectable(c_id: u16, ns_id: u32, lba: u64, blocks_1: u16) -> Self {
+        Self {
+            opcode: 4,
+            ..Self::io_read(c_id, ns_id, lba, blocks_1, 0, 0)
+        }
+    }
+
+    /// Copy of the blocks of `ranges` source range descriptors at `ptr` to the blocks starting at `dest_lba`
+    pub const fn copy(c_id: u16, ns_id: u32, ptr: u64, ranges: u16, dest_lba: u64) -> Self {
+        Self {
+            opcode: 0x19,
+            flags: 0,
+            c_id,
+            ns_id,
+            _rsvd: 0,
+            md_ptr: 0,
+            d_ptr: [ptr, 0],
+            cdw10: dest_lba as u32,
+            cdw11: (dest_lba >> 32) as u32,
+            // NR is 0's based, descriptor format 0
+            cdw12: (ranges - 1) as u32,
+            cdw13: 0,
+            cdw14: 0,
+            cdw15: 0,
+        }
+    }
+
+    /// Dataset Management of `ranges` range descriptors at `ptr`
+    pub const fn dataset_management(
+        c_id: u16,
+        ns_id: u32,
+        ptr: u64,
+        ranges: u16,
+        attributes: DsmAttributes,
+    ) -> Self {
+        Self {
+            opcode: 9,
+            flags: 0,
+            c_id,
+            ns_id,
+            _rsvd: 0,
+            md_ptr: 0,
+            d_ptr: [ptr, 0],
+            // NR is 0's based
+            cdw10: (ranges - 1) as u32,
+            cdw11: attributes.cdw11(),
+            cdw12: 0,
+            cdw13: 0,
+            cdw14: 0,
+            cdw15: 0,
+        }
+    }
+
     pub(crate) const fn format_nvm(c_id: u16, ns_id: u32) -> Self {
         Self {
             opcode: 0x80,
@@ -260,7 +394,7 @@ impl NvmeCommand {
         }
     }
 
-    // not supported by samsung
+    // not supported by samsung, check ONCS
     pub const fn write_zeroes(c_id: u16, ns_id: u32, slba: u64, nlb: u16, deac: bool) -> Self {
         Self {
             opcode: 8,
@@ -279,4 +413,237 @@ impl NvmeCommand {
             cdw15: 0,
         }
     }
+
+    /// Enables interrupts of a completion queue created by this command, signalled on MSI-X vector `iv`
+    #[must_use]
+    pub const fn with_interrupt_vector(mut self, iv: u16) -> Self {
+        self.cdw11 |= ((iv as u32) << 16) | 0b10;
+        self
+    }
+
+    /// Sets the priority class QPRIO of a submission queue created by this command, only used with weighted round robin arbitration
+    #[must_use]
+    pub const fn with_queue_priority(mut self, priority: u8) -> Self {
+        self.cdw11 |= ((priority as u32) & 0b11) << 1;
+        self
+    }
+
+    /// Sets Force Unit Access of a read or write, the command completes only once its data is in non-volatile media
+    #[must_use]
+    pub const fn with_fua(mut self) -> Self {
+        self.cdw12 |= 1 << 30;
+        self
+    }
+
+    /// Sets the protection information handling (PRINFO) of a read, write, compare or write zeroes,
+    /// and the (expected) initial reference tag, storage tag, application tag and mask of its blocks in protection information `format`
+    #[must_use]
+    #[allow(clippy::used_underscore_binding)]
+    pub const fn with_protection(
+        mut self,
+        protection: Protection,
+        format: Option<PiFormat>,
+    ) -> Self {
+        let tag_space = protection.tag_space(format);
+        self.cdw12 = (self.cdw12 & !(0xF << 26)) | (protection.prinfo() << 26);
+        // the lower 32 bits of the storage and reference space, the upper ones of 80 bit spaces are in dwords 2 and 3
+        self.cdw14 = tag_space as u32;
+        self._rsvd = (tag_space >> 32) as u64;
+        self.cdw15 =
+            ((protection.application_mask as u32) << 16) | protection.application_tag as u32;
+        self
+    }
+
+    /// Sets the metadata pointer to a physically contiguous metadata buffer
+    #[must_use]
+    pub const fn with_metadata(mut self, md_ptr: u64) -> Self {
+        self.md_ptr = md_ptr;
+        self
+    }
+
+    /// FUSE value of the first command of a fused operation
+    pub const FUSE_FIRST: u8 = 0b01;
+    /// FUSE value of the second command of a fused operation
+    pub const FUSE_SECOND: u8 = 0b10;
+
+    /// Sets the FUSE bits of the command, making it part of a fused operation
+    #[must_use]
+    pub const fn with_fuse(mut self, fuse: u8) -> Self {
+        self.flags = (self.flags & !0b11) | (fuse & 0b11);
+        self
+    }
+
+    /// Whether the command is part of a fused operation
+    pub const fn is_fused(&self) -> bool {
+        self.flags & 0b11 != 0
+    }
+
+    /// PSDT value for SGLs with a physically contiguous metadata buffer
+    const PSDT_SGL: u8 = 0b01 << 6;
+
+    /// Replaces the data pointer of the command with the SGL descriptor `sgl1` and sets PSDT accordingly
+    #[must_use]
+    pub const fn with_sgl(mut self, sgl1: SglDescriptor) -> Self {
+        self.flags = (self.flags & 0x3F) | Self::PSDT_SGL;
+        self.d_ptr = sgl1.to_dptr();
+        self
+    }
+}
+
+/// `NVMe` spec 4.4
+/// Scatter gather list descriptor
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, packed)]
+pub struct SglDescriptor {
+    /// Address
+    pub addr: u64,
+    /// Length
+    pub length: u32,
+    /// Reserved
+    _rsvd: [u8; 3],
+    /// SGL identifier; Descriptor type (4 bits) | Descriptor sub type (4 bits)
+    pub sgl_id: u8,
+}
+
+impl SglDescriptor {
+    const DATA_BLOCK: u8 = 0x0;
+    const BIT_BUCKET: u8 = 0x1;
+    const LAST_SEGMENT: u8 = 0x3;
+
+    pub const fn data_block(addr: u64, length: u32) -> Self {
+        Self {
+            addr,
+            length,
+            _rsvd: [0; 3],
+            sgl_id: Self::DATA_BLOCK << 4,
+        }
+    }
+
+    /// Discards `length` bytes of read data, only valid inside of segments
+    pub const fn bit_bucket(length: u32) -> Self {
+        Self {
+            addr: 0,
+            length,
+            _rsvd: [0; 3],
+            sgl_id: Self::BIT_BUCKET << 4,
+        }
+    }
+
+    /// Points to the final segment of `descriptors` descriptors
+    pub const fn last_segment(addr: u64, descriptors: u32) -> Self {
+        Self {
+            addr,
+            length: descriptors * mem::size_of::<Self>() as u32,
+            _rsvd: [0; 3],
+            sgl_id: Self::LAST_SEGMENT << 4,
+        }
+    }
+
+    /// Layout of the descriptor as SGL1 in the data pointer of a command
+    pub const fn to_dptr(self) -> [u64; 2] {
+        [self.addr, ((self.sgl_id as u64) << 56) | self.length as u64]
+    }
+}
+
+/// `NVMe` NVM command set spec 3.2.3
+/// Range of a Dataset Management command
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct DsmRange {
+    /// Context attributes, hints on the expected access of the range
+    pub context_attributes: u32,
+    /// Length in logical blocks
+    pub blocks: u32,
+    /// Starting LBA
+    pub lba: u64,
+}
+
+impl DsmRange {
+    /// Maximum amount of ranges of a single Dataset Management command
+    pub const MAX_RANGES: usize = 256;
+
+    #[must_use]
+    pub const fn new(lba: u64, blocks: u32) -> Self {
+        Self {
+            context_attributes: 0,
+            blocks,
+            lba,
+        }
+    }
+}
+
+/// `NVMe` NVM command set spec 3.3.1.1
+/// Source range of a Copy command, in descriptor format 0
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct CopyRange {
+    _rsvd: u64,
+    lba: u64,
+    // 0's based
+    blocks_1: u16,
+    _rsvd2: u16,
+    _rsvd3: u32,
+    // end-to-end protection fields, unused without protection information
+    eilbrt: u32,
+    elbat: u16,
+    elbatm: u16,
+}
+
+const _: () = assert!(mem::size_of::<CopyRange>() == 32);
+
+impl CopyRange {
+    /// # Panics
+    /// Panics if `blocks` is 0 or exceeds the 65536 blocks a range may cover
+    #[must_use]
+    pub const fn new(lba: u64, blocks: u32) -> Self {
+        assert!(blocks > 0 && blocks <= 1 << 16);
+        Self {
+            _rsvd: 0,
+            lba,
+            blocks_1: (blocks - 1) as u16,
+            _rsvd2: 0,
+            _rsvd3: 0,
+            eilbrt: 0,
+            elbat: 0,
+            elbatm: 0,
+        }
+    }
+
+    /// Starting LBA
+    #[must_use]
+    pub const fn lba(&self) -> u64 {
+        self.lba
+    }
+
+    /// Length in logical blocks
+    #[must_use]
+    pub const fn blocks(&self) -> u32 {
+        self.blocks_1 as u32 + 1
+    }
+}
+
+/// Attributes of a Dataset Management command, applying to all of its ranges
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DsmAttributes {
+    /// The ranges are going to be read as a whole (IDR)
+    pub integral_read: bool,
+    /// The ranges are going to be written as a whole (IDW)
+    pub integral_write: bool,
+    /// The ranges are no longer needed and may be deallocated (AD)
+    pub deallocate: bool,
+}
+
+impl DsmAttributes {
+    /// Deallocates the ranges, like TRIM
+    pub const DEALLOCATE: Self = Self {
+        integral_read: false,
+        integral_write: false,
+        deallocate: true,
+    };
+
+    const fn cdw11(self) -> u32 {
+        ((self.deallocate as u32) << 2)
+            | ((self.integral_write as u32) << 1)
+            | (self.integral_read as u32)
+    }
 }
@@ -1,6 +1,9 @@
 use std::fmt;
 use std::io;
 
+use crate::protection::PiField;
+use crate::status::NvmeStatus;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
@@ -8,10 +11,47 @@ pub enum Error {
     Vroom(String),
     Io(io::Error),
     Allocation(String),
-    Mmap { error: String, io_error: io::Error },
-    Ioctl { error: String, io_error: io::Error },
+    Mmap {
+        error: String,
+        io_error: io::Error,
+    },
+    Ioctl {
+        error: String,
+        io_error: io::Error,
+    },
     Vfio(String),
     Mmio(String),
+    Nvme(NvmeStatus),
+    Timeout(String),
+    /// The compare of a compare or fused compare and write found different data on the device
+    CompareFailure,
+    /// A data buffer whose length is not a multiple of the block size of the namespace it is transferred to or from
+    BlockSize {
+        len: usize,
+        block_size: u64,
+    },
+    /// A data buffer which is not dword aligned, as required of data pointers
+    Misaligned {
+        addr: usize,
+    },
+    /// A range of blocks which exceeds the namespace it addresses
+    OutOfRange {
+        ns_id: u32,
+        lba: u64,
+        blocks: u64,
+        capacity: u64,
+    },
+    /// A transfer without metadata buffer to or from a namespace with separate metadata
+    MetadataRequired {
+        ns_id: u32,
+    },
+    /// Protection information of a block which differs from what was expected, found by the host
+    Protection {
+        block: u64,
+        field: PiField,
+        expected: u64,
+        found: u64,
+    },
 }
 
 impl std::error::Error for Error {}
@@ -30,6 +70,38 @@ impl fmt::Display for Error {
             }
             Self::Vfio(error) => write!(f, "Vfio Error: {error}"),
             Self::Mmio(error) => write!(f, "Mmio Error: {error}"),
+            Self::Nvme(status) => write!(f, "NVMe Error: {status}"),
+            Self::Timeout(error) => write!(f, "Timeout Error: {error}"),
+            Self::CompareFailure => write!(f, "Compare Failure: data on the device differs"),
+            Self::BlockSize { len, block_size } => write!(
+                f,
+                "Block Size Error: buffer of {len} bytes is not a multiple of the block size {block_size}"
+            ),
+            Self::Misaligned { addr } => {
+                write!(f, "Alignment Error: buffer at {addr:#x} is not dword aligned")
+            }
+            Self::OutOfRange {
+                ns_id,
+                lba,
+                blocks,
+                capacity,
+            } => write!(
+                f,
+                "Range Error: {blocks} blocks at LBA {lba} exceed the {capacity} blocks of namespace {ns_id}"
+            ),
+            Self::MetadataRequired { ns_id } => write!(
+                f,
+                "Metadata Error: namespace {ns_id} needs a metadata buffer for its separate metadata"
+            ),
+            Self::Protection {
+                block,
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Protection Error: {field} of block {block} is {found:#x}, expected {expected:#x}"
+            ),
         }
     }
 }
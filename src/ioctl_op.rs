@@ -17,10 +17,7 @@ pub enum IoctlOp {
     VFIO_IOMMU_UNMAP_DMA,
 
     // constants needed for IOMMU Interrupts.
-    #[allow(unused)]
     VFIO_DEVICE_GET_IRQ_INFO,
-
-    #[allow(unused)]
     VFIO_DEVICE_SET_IRQS,
 
     // VFIO IOMMUFD constants
@@ -97,10 +94,8 @@ impl IoctlFlag {
     #[allow(unused)]
     pub const VFIO_IRQ_SET_DATA_NONE: u32 = 1 << 0; /* Data not present */
 
-    #[allow(unused)]
     pub const VFIO_IRQ_SET_DATA_EVENTFD: u32 = 1 << 2; /* Data is eventfd (s32) */
 
-    #[allow(unused)]
     pub const VFIO_IRQ_SET_ACTION_TRIGGER: u32 = 1 << 5; /* Trigger interrupt */
 
     #[allow(unused)]
@@ -6,19 +6,25 @@
     clippy::module_name_repetitions
 )]
 #![cfg_attr(target_arch = "aarch64", feature(stdarch_arm_hints))]
+pub mod async_qpair;
+mod cmb;
 #[allow(unused)]
 mod cmd;
 mod error;
 pub mod mapping;
 #[allow(dead_code)]
 pub mod memory;
+mod notify;
 #[allow(dead_code)]
 mod nvme;
 #[allow(dead_code)]
 mod pci;
 mod physical;
+mod pmr;
+mod protection;
 #[allow(dead_code)]
 mod queues;
+pub mod status;
 pub mod vfio;
 
 #[allow(dead_code, clippy::identity_op)]
@@ -40,11 +46,21 @@ pub use memory::PAGESIZE_4KIB;
 pub use mapping::Mapping;
 pub use mapping::MemoryAccess;
 
-pub use nvme::{NvmeDevice, NvmeQueuePair};
+pub use async_qpair::AsyncQueuePair;
+pub use cmb::ControllerMemoryBuffer;
+pub use cmd::{CopyRange, DsmAttributes, DsmRange};
+pub use nvme::{
+    ArbitrationWeights, NvmeCompletedRequest, NvmeDevice, NvmeNamespace, NvmeQueuePair,
+    NvmeRequest, NvmeStats, PollStrategy, QueuePriority, RetryPolicy,
+};
 use pci::{pci_open_resource_ro, read_hex, read_io32};
+pub use pmr::PersistentMemoryRegion;
+pub use protection::{crc16_t10dif, crc32c, PiField, PiFormat, PiGuard, PiType, Protection};
+#[allow(deprecated)]
 pub use queues::QUEUE_LENGTH;
 
 pub use error::{Error, Result};
+pub use status::NvmeStatus;
 
 /// initialise driver
 /// # Arguments
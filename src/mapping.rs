@@ -44,6 +44,15 @@ impl MemoryAccess {
         })
     }
 
+    /// Maps BAR `index` of the device into host memory
+    /// # Errors
+    pub fn map_bar(&self, index: u32) -> Result<(*mut u8, usize)> {
+        match self {
+            Self::Physical(mmio) => mmio.map_resource_index(index),
+            Self::Vfio(vfio) => vfio.map_resource_index(index),
+        }
+    }
+
     pub fn set_page_size(&mut self, page_size: Pagesize) {
         if let Self::Vfio(vfio) = self {
             vfio.set_page_size(page_size);
@@ -0,0 +1,118 @@
+use crate::{Error, Result};
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+use std::{io, mem, ptr};
+
+/// Blocks until the eventfd or timerfd `fd` is readable or `timeout` passed, and resets its counter.
+/// Returns whether it was signalled.
+/// # Errors
+pub fn wait(fd: RawFd, timeout: Option<Duration>) -> Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // round up, so the timeout passed once poll returns
+    let timeout = timeout.map_or(-1, |timeout| {
+        timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+    });
+
+    match unsafe { libc::poll(ptr::addr_of_mut!(pollfd), 1, timeout) } {
+        -1 => {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(Error::Io(error))
+            }
+        }
+        0 => Ok(false),
+        _ => {
+            let mut counter = 0u64;
+            unsafe {
+                libc::read(
+                    fd,
+                    ptr::addr_of_mut!(counter).cast::<libc::c_void>(),
+                    mem::size_of::<u64>(),
+                )
+            };
+            Ok(true)
+        }
+    }
+}
+
+/// Timerfd expiring periodically while armed.
+/// Makes queue pairs without interrupts usable from event loops, which poll the queue whenever it fires.
+pub struct PollTimer {
+    fd: OwnedFd,
+    interval: Duration,
+    armed: bool,
+}
+
+impl PollTimer {
+    /// # Errors
+    /// Returns an error if `interval` is zero or the timerfd can't be created
+    pub fn new(interval: Duration) -> Result<Self> {
+        if interval.is_zero() {
+            return Err("poll interval must not be zero".into());
+        }
+        let fd = unsafe {
+            libc::timerfd_create(
+                libc::CLOCK_MONOTONIC,
+                libc::TFD_NONBLOCK | libc::TFD_CLOEXEC,
+            )
+        };
+        if fd == -1 {
+            return Err(Error::Io(io::Error::last_os_error()));
+        }
+
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            interval,
+            armed: false,
+        })
+    }
+
+    /// Starts the timer, if it isn't running already
+    pub fn arm(&mut self) {
+        if !self.armed {
+            self.set(self.interval);
+            self.armed = true;
+        }
+    }
+
+    /// Stops the timer, if it is running
+    pub fn disarm(&mut self) {
+        if self.armed {
+            self.set(Duration::ZERO);
+            self.armed = false;
+        }
+    }
+
+    // timerfd_settime only fails for invalid arguments, a zero interval disarms the timer
+    fn set(&self, interval: Duration) {
+        let interval = libc::timespec {
+            tv_sec: interval.as_secs() as libc::time_t,
+            tv_nsec: libc::c_long::from(interval.subsec_nanos()),
+        };
+        let spec = libc::itimerspec {
+            it_interval: interval,
+            it_value: interval,
+        };
+        unsafe {
+            libc::timerfd_settime(self.fd.as_raw_fd(), 0, ptr::addr_of!(spec), ptr::null_mut())
+        };
+    }
+}
+
+impl AsFd for PollTimer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for PollTimer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
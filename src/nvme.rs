@@ -1,12 +1,20 @@
-use crate::cmd::NvmeCommand;
+use crate::cmb::ControllerMemoryBuffer;
+use crate::cmd::{CopyRange, DsmAttributes, DsmRange, NvmeCommand, SglDescriptor};
 use crate::mapping::{Mapping, MemoryAccess};
 use crate::memory::{Dma, DmaSlice, Pagesize};
-use crate::queues::{CompletionQueue, NvmeCompletion, SubmissionQueue, QUEUE_LENGTH};
-use crate::Result;
+use crate::notify::{self, PollTimer};
+use crate::pci::read_bar_address;
+use crate::pmr::{PersistentMemoryRegion, PmrCapabilities};
+use crate::protection::{PiFormat, PiGuard, PiType, Protection};
+use crate::queues::{CommandIds, CompletionQueue, NvmeCompletion, SubmissionQueue};
+use crate::status::NvmeStatus;
+use crate::{Error, Result};
 use crate::{PAGESIZE_2MIB, PAGESIZE_4KIB};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::hint::spin_loop;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 #[allow(unused, clippy::upper_case_acronyms)]
@@ -61,7 +69,7 @@ struct IdentifyNamespaceData {
     pub flbas: u8,
     mc: u8,
     dpc: u8,
-    dps: u8,
+    pub dps: u8,
     nmic: u8,
     rescap: u8,
     fpi: u8,
@@ -79,7 +87,10 @@ struct IdentifyNamespaceData {
     npdg: u16,
     npda: u16,
     nows: u16,
-    _rsvd1: [u8; 18],
+    pub mssrl: u16,
+    pub mcl: u32,
+    pub msrc: u8,
+    _rsvd1: [u8; 11],
     anagrpid: u32,
     _rsvd2: [u8; 3],
     nsattr: u8,
@@ -92,151 +103,1324 @@ struct IdentifyNamespaceData {
     vendor_specific: [u8; 3712],
 }
 
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+#[allow(unused)]
+struct IdentifyControllerData {
+    vid: u16,
+    ssvid: u16,
+    sn: [u8; 20],
+    mn: [u8; 40],
+    fr: [u8; 8],
+    rab: u8,
+    ieee: [u8; 3],
+    cmic: u8,
+    pub mdts: u8,
+    cntlid: u16,
+    ver: u32,
+    rtd3r: u32,
+    rtd3e: u32,
+    oaes: u32,
+    ctratt: u32,
+    rrls: u16,
+    _rsvd1: [u8; 9],
+    cntrltype: u8,
+    fguid: [u8; 16],
+    crdt1: u16,
+    crdt2: u16,
+    crdt3: u16,
+    _rsvd2: [u8; 119],
+    nvmsr: u8,
+    vwci: u8,
+    mec: u8,
+    oacs: u16,
+    acl: u8,
+    aerl: u8,
+    frmw: u8,
+    lpa: u8,
+    elpe: u8,
+    npss: u8,
+    avscc: u8,
+    apsta: u8,
+    wctemp: u16,
+    cctemp: u16,
+    mtfa: u16,
+    hmpre: u32,
+    hmmin: u32,
+    tnvmcap: u128,
+    unvmcap: u128,
+    rpmbs: u32,
+    edstt: u16,
+    dsto: u8,
+    fwug: u8,
+    kas: u16,
+    hctma: u16,
+    mntmt: u16,
+    mxtmt: u16,
+    sanicap: u32,
+    hmminds: u32,
+    hmmaxd: u16,
+    nsetidmax: u16,
+    endgidmax: u16,
+    anatt: u8,
+    anacap: u8,
+    anagrpmax: u32,
+    nanagrpid: u32,
+    pels: u32,
+    _rsvd3: [u8; 156],
+    sqes: u8,
+    cqes: u8,
+    maxcmd: u16,
+    nn: u32,
+    oncs: u16,
+    fuses: u16,
+    fna: u8,
+    vwc: u8,
+    awun: u16,
+    awupf: u16,
+    icsvscc: u8,
+    nwpc: u8,
+    acwu: u16,
+    _rsvd4: [u8; 2],
+    sgls: u32,
+    mnan: u32,
+    _rsvd5: [u8; 224],
+    subnqn: [u8; 256],
+    _rsvd6: [u8; 768],
+    nvmeof: [u8; 256],
+    psd: [u8; 1024],
+    vendor_specific: [u8; 1024],
+}
+
+/// Handle of a request submitted to a queue pair, reported again once all of its commands completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NvmeRequest {
+    sq_id: u16,
+    // command id of the first command of the request
+    c_id: u16,
+}
+
+impl NvmeRequest {
+    /// Submission queue the request was submitted to
+    #[must_use]
+    pub const fn sq_id(&self) -> u16 {
+        self.sq_id
+    }
+}
+
+/// A request which finished, as reported by polling its queue pair
+#[derive(Debug, Clone, Copy)]
+pub struct NvmeCompletedRequest {
+    pub request: NvmeRequest,
+    /// Submission queue the request was submitted to
+    pub sq_id: u16,
+    /// Status of the first failed command of the request, success otherwise
+    pub status: NvmeStatus,
+}
+
+impl NvmeCompletedRequest {
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.status.is_success()
+    }
+
+    /// # Errors
+    /// Returns `Error::CompareFailure` if a compare of the request found different data, `Error::Nvme` if the request failed otherwise
+    pub fn result(&self) -> Result<()> {
+        if self.is_success() {
+            Ok(())
+        } else if self.status.is_compare_failure() {
+            Err(Error::CompareFailure)
+        } else {
+            Err(Error::Nvme(self.status))
+        }
+    }
+}
+
+/// Submission queue of a queue pair, with the commands submitted to it
+struct IoSubmissionQueue {
+    id: u16,
+    queue: SubmissionQueue,
+    c_ids: CommandIds,
+    // one list per command id, holds the PRP list, SGL segment or range descriptors of the command
+    prp_lists: Dma<u8>,
+    // bytes of each list, enough for the PRP list of a transfer of the maximum size
+    list_size: usize,
+}
+
+impl IoSubmissionQueue {
+    fn ring_doorbell(&self) {
+        unsafe {
+            std::ptr::write_volatile(self.queue.doorbell as *mut u32, self.queue.tail as u32);
+        }
+    }
+
+    /// Physical address of the list of command `c_id`
+    const fn list_addr(&self, c_id: u16) -> u64 {
+        (self.prp_lists.phys + c_id as usize * self.list_size) as u64
+    }
+
+    /// Number of entries of type `T` the list of a command holds
+    const fn list_entries<T>(&self) -> usize {
+        self.list_size / std::mem::size_of::<T>()
+    }
+
+    /// The list of command `c_id`, as entries of type `T`
+    const fn list<T: Copy>(&mut self, c_id: u16) -> &mut [T] {
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.prp_lists
+                    .virt
+                    .add(c_id as usize * self.list_size)
+                    .cast::<T>(),
+                self.list_entries::<T>(),
+            )
+        }
+    }
+
+    /// Returns PRP1 and PRP2 for a transfer of `bytes` starting at `addr`.
+    /// Transfers spanning more than two memory pages get their PRP list built in the list of command `c_id`.
+    fn prp_entries(&mut self, c_id: u16, addr: u64, bytes: u64) -> [u64; 2] {
+        let page_size = PAGESIZE_4KIB as u64;
+        // only PRP1 may have an offset into its page, every following entry is page aligned
+        let offset = addr & (page_size - 1);
+        let pages = (offset + bytes).div_ceil(page_size);
+        let next_page = addr - offset + page_size;
+
+        match pages {
+            0 | 1 => [addr, 0],
+            2 => [addr, next_page],
+            _ => {
+                let list = self.list::<u64>(c_id);
+                for (i, entry) in list.iter_mut().take(pages as usize - 1).enumerate() {
+                    *entry = next_page + i as u64 * page_size;
+                }
+                [addr, self.list_addr(c_id)]
+            }
+        }
+    }
+}
+
+/// Completion queue with the submission queues attached to it, the first one shares the id of the completion queue
 pub struct NvmeQueuePair {
     pub id: u16,
-    pub sub_queue: SubmissionQueue,
     comp_queue: CompletionQueue,
+    sub_queues: Vec<IoSubmissionQueue>,
+    max_transfer_size: usize,
+    sgls: u32,
+    oncs: u16,
+    fuses: u16,
+    // bytes a single Write Zeroes command may cover, 0 if only limited by NLB
+    write_zeroes_limit: usize,
+    // bytes a single Verify or Write Uncorrectable command may cover, 0 if only limited by NLB
+    verify_limit: usize,
+    write_uncorrectable_limit: usize,
+    // whether flushes have anything to commit
+    volatile_write_cache: bool,
+    // source of writes emulating Write Zeroes, if the controller doesn't support it
+    zeroes: Option<Arc<Dma<u8>>>,
+    // command retry delay times from identify controller, in 100 ms units
+    crdt: [u16; 3],
+    // submission queue indices and command ids to resubmit, with the earliest time to do so
+    pending_retries: Vec<(Instant, usize, u16)>,
+    // requests which completed while waiting for a request that timed out
+    ready: VecDeque<NvmeCompletedRequest>,
+    // eventfd signalled by the MSI-X vector of the completion queue, if interrupts are enabled
+    interrupt: Option<RawFd>,
+    // timer making the queue pollable from event loops if interrupts are disabled
+    poll_timer: Option<PollTimer>,
+    pub retry_policy: RetryPolicy,
+    /// Maximum time a command may be outstanding at the controller, `None` to wait forever
+    pub command_timeout: Option<Duration>,
+    pub poll_strategy: PollStrategy,
+    // moving average of the command latencies, only tracked for polling strategies which need it
+    latency_estimate: Option<Duration>,
+    pub stats: NvmeStats,
+}
+
+/// How `NvmeQueuePair::complete_io` waits on an empty completion queue.
+/// The strategies besides `Spin` are based on the expected completion time, learned from recent command latencies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PollStrategy {
+    /// Busy poll the completion queue
+    #[default]
+    Spin,
+    /// Busy poll until the expected completion time passed, then yield the thread between polls
+    SpinThenYield,
+    /// Sleep through the first half of the expected completion time like Linux hybrid polling,
+    /// busy poll until it passed and sleep for a quarter of it between polls afterwards
+    SpinThenSleep,
+}
+
+/// Priority class of a submission queue under weighted round robin arbitration.
+///
+/// Urgent queues are served strictly before all others, the remaining classes share the controller by their arbitration weights.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueuePriority {
+    Urgent = 0,
+    High = 1,
+    #[default]
+    Medium = 2,
+    Low = 3,
+}
+
+/// Weights of the priority classes under weighted round robin arbitration (Arbitration feature)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArbitrationWeights {
+    /// Commands taken from a queue at once, as a power of two, 7 for no limit
+    pub burst: u8,
+    /// Commands taken from high priority queues per round, 1 to 256
+    pub high: u16,
+    /// Commands taken from medium priority queues per round, 1 to 256
+    pub medium: u16,
+    /// Commands taken from low priority queues per round, 1 to 256
+    pub low: u16,
+}
+
+impl Default for ArbitrationWeights {
+    fn default() -> Self {
+        Self {
+            burst: 0,
+            high: 16,
+            medium: 4,
+            low: 1,
+        }
+    }
+}
+
+impl ArbitrationWeights {
+    /// Value of dword 11 of Set Features, the weights are 0's based
+    fn cdw11(self) -> u32 {
+        let weight = |w: u16| u32::from(w.clamp(1, 256) - 1);
+        (weight(self.high) << 24)
+            | (weight(self.medium) << 16)
+            | (weight(self.low) << 8)
+            | (u32::from(self.burst) & 0b111)
+    }
 }
 
 unsafe impl Send for NvmeQueuePair {}
 
+/// Blocks of `block_size` bytes a single command may cover, given its size limit in bytes
+const fn limit_blocks(limit: usize, block_size: u64) -> u64 {
+    if limit == 0 || block_size == 0 {
+        MAX_COMMAND_BLOCKS
+    } else {
+        limit as u64 / block_size
+    }
+}
+
+/// Largest multiple of the block size of `ns` not exceeding `max_bytes` nor the blocks a single command may cover,
+/// the size data is split into commands at
+const fn chunk_size(ns: &NvmeNamespace, max_bytes: usize) -> usize {
+    let block_size = ns.block_size as usize;
+    match max_bytes.checked_div(block_size) {
+        Some(blocks) if blocks > MAX_COMMAND_BLOCKS as usize => {
+            MAX_COMMAND_BLOCKS as usize * block_size
+        }
+        Some(blocks) => blocks * block_size,
+        None => 0,
+    }
+}
+
+/// Chunk size of the device functions transferring buffers of the caller starting `offset` bytes into a page,
+/// whose chunks have to fit into the two pages of PRP1 and PRP2.
+/// The blocks of extended LBAs aren't page aligned, so their chunks may start anywhere in a page, as do all chunks of unaligned buffers.
+/// 0 if a single block doesn't fit, which then has to be transferred through the device buffer.
+const fn prp_chunk_size(ns: &NvmeNamespace, offset: usize) -> usize {
+    if ns.extended_lba || offset != 0 {
+        chunk_size(ns, PAGESIZE_4KIB)
+    } else {
+        chunk_size(ns, 2 * PAGESIZE_4KIB)
+    }
+}
+
+/// Checks that `data` consists of whole blocks of `ns`, starts dword aligned and needs no metadata buffer, before any of it is submitted.
+/// Returns the amount of blocks of `data`.
+fn check_buffer(ns: &NvmeNamespace, data: &impl DmaSlice, chunk_size: usize) -> Result<u64> {
+    ns.check_metadata()?;
+    check_data(ns, data, chunk_size)
+}
+
+/// Checks that `data` consists of whole blocks of `ns` and starts dword aligned.
+/// Returns the amount of blocks of `data`.
+fn check_data(ns: &NvmeNamespace, data: &impl DmaSlice, chunk_size: usize) -> Result<u64> {
+    if chunk_size == 0 {
+        return Err(format!(
+            "block size {} of namespace {} is not supported",
+            ns.block_size, ns.id
+        )
+        .into());
+    }
+    let mut len = 0;
+    for chunk in data.chunks(chunk_size) {
+        if len == 0 && chunk.phys_addr % 4 != 0 {
+            return Err(Error::Misaligned {
+                addr: chunk.phys_addr,
+            });
+        }
+        len += chunk.slice.len();
+    }
+    if len == 0 || !(len as u64).is_multiple_of(ns.block_size) {
+        return Err(Error::BlockSize {
+            len,
+            block_size: ns.block_size,
+        });
+    }
+    Ok(len as u64 / ns.block_size)
+}
+
+/// Checks that `len` bytes are whole blocks of `ns`, for transfers through the device buffer.
+/// Returns the amount of blocks.
+fn check_batch(ns: &NvmeNamespace, len: usize) -> Result<u64> {
+    ns.check_metadata()?;
+    if len == 0 || ns.block_size == 0 || !(len as u64).is_multiple_of(ns.block_size) {
+        return Err(Error::BlockSize {
+            len,
+            block_size: ns.block_size,
+        });
+    }
+    Ok(len as u64 / ns.block_size)
+}
+
+fn check_copy(
+    oncs: u16,
+    ns: &NvmeNamespace,
+    ranges: &[CopyRange],
+    dest_lba: u64,
+    list_entries: usize,
+) -> Result<()> {
+    if oncs & ONCS_COPY == 0 {
+        return Err("controller does not support Copy".into());
+    }
+    // the descriptors are placed in the list of the command
+    let max_ranges = usize::from(ns.max_copy_ranges).min(list_entries);
+    if ranges.is_empty() || ranges.len() > max_ranges {
+        return Err(format!("Copy takes 1 to {max_ranges} ranges, got {}", ranges.len()).into());
+    }
+    let max_range = ns.max_copy_range_blocks;
+    if max_range != 0
+        && ranges
+            .iter()
+            .any(|range| range.blocks() > u32::from(max_range))
+    {
+        return Err(format!("Copy source ranges may cover at most {max_range} blocks").into());
+    }
+    let blocks: u64 = ranges.iter().map(|range| u64::from(range.blocks())).sum();
+    if ns.max_copy_blocks != 0 && blocks > u64::from(ns.max_copy_blocks) {
+        return Err(format!(
+            "Copy may cover at most {} blocks, got {blocks}",
+            ns.max_copy_blocks
+        )
+        .into());
+    }
+    for range in ranges {
+        ns.check_range(range.lba(), u64::from(range.blocks()))?;
+    }
+    ns.check_range(dest_lba, blocks)
+}
+
+fn check_dataset_management(
+    oncs: u16,
+    ns: &NvmeNamespace,
+    ranges: &[DsmRange],
+    max_ranges: usize,
+) -> Result<()> {
+    if oncs & ONCS_DSM == 0 {
+        return Err("controller does not support Dataset Management".into());
+    }
+    if ranges.is_empty() || ranges.len() > max_ranges {
+        return Err(format!(
+            "Dataset Management takes 1 to {max_ranges} ranges, got {}",
+            ranges.len()
+        )
+        .into());
+    }
+    for range in ranges {
+        ns.check_range(range.lba, u64::from(range.blocks))?;
+    }
+    Ok(())
+}
+
 impl NvmeQueuePair {
-    /// returns amount of requests pushed into submission queue
-    pub fn submit_io(&mut self, data: &impl DmaSlice, mut lba: u64, write: bool) -> usize {
-        let mut reqs = 0;
-        // TODO: contruct PRP list?
-        for chunk in data.chunks(2 * 4096) {
-            let blocks = (chunk.slice.len() as u64 + 512 - 1) / 512;
-            let addr = chunk.phys_addr as u64;
-            let bytes = blocks * 512;
-            let ptr1 = if bytes <= 4096 {
-                0
-            } else {
-                addr + 4096 // self.page_size
-            };
+    /// Submits a read or write of `data` starting at `lba` of `ns`, split into commands of at most the maximum data transfer size.
+    /// # Errors
+    /// Returns an error if `data` isn't made up of whole blocks of `ns` or the queue can't take all commands of the request
+    pub fn submit_io(
+        &mut self,
+        ns: &NvmeNamespace,
+        data: &impl DmaSlice,
+        lba: u64,
+        write: bool,
+    ) -> Result<NvmeRequest> {
+        self.submit_io_on(self.id, ns, data, lba, write)
+    }
 
-            let entry = if write {
-                NvmeCommand::io_write(
-                    self.id << 11 | self.sub_queue.tail as u16,
-                    1,
-                    lba,
-                    blocks as u16 - 1,
-                    addr,
-                    ptr1,
-                )
-            } else {
-                NvmeCommand::io_read(
-                    self.id << 11 | self.sub_queue.tail as u16,
-                    1,
-                    lba,
-                    blocks as u16 - 1,
-                    addr,
-                    ptr1,
-                )
-            };
+    /// Submits a read or write as `submit_io`, to the attached submission queue `sq_id`
+    /// # Errors
+    /// Returns an error if `sq_id` isn't attached, `data` isn't made up of whole blocks of `ns` or the queue is full
+    pub fn submit_io_on(
+        &mut self,
+        sq_id: u16,
+        ns: &NvmeNamespace,
+        data: &impl DmaSlice,
+        lba: u64,
+        write: bool,
+    ) -> Result<NvmeRequest> {
+        if self.sq_index(sq_id).is_none() {
+            return Err(format!("submission queue {sq_id} is not attached").into());
+        }
+        self.check_io(ns, data, lba)?;
+        self.submit_namespace_io(sq_id, ns, data, lba, write)
+            .ok_or_else(|| "queue full".into())
+    }
 
-            if let Some(tail) = self.sub_queue.submit_checked(entry) {
-                unsafe {
-                    std::ptr::write_volatile(self.sub_queue.doorbell as *mut u32, tail as u32);
+    /// Submits a read or write of `data` with end-to-end data protection as directed by `protection`.
+    /// The metadata of the blocks is transferred from or to `metadata`, or interleaved in `data` with extended LBAs.
+    /// Without `metadata` the controller has to insert and strip the protection information, which `protection.action` requests.
+    /// Type 1 reference tags are the lower 32 bits of the LBA of each block.
+    /// # Errors
+    /// Returns an error if `ns` has no protection information, `metadata` doesn't match the blocks of `data`,
+    /// `data` isn't made up of whole blocks of `ns` or the queue can't take all commands of the request
+    pub fn submit_io_pi(
+        &mut self,
+        ns: &NvmeNamespace,
+        data: &impl DmaSlice,
+        metadata: Option<&Dma<u8>>,
+        lba: u64,
+        write: bool,
+        protection: Protection,
+    ) -> Result<NvmeRequest> {
+        let format = ns
+            .protection
+            .ok_or_else(|| format!("namespace {} has no protection information", ns.id))?;
+        let blocks = check_data(ns, data, chunk_size(ns, self.max_transfer_size))?;
+        ns.check_range(lba, blocks)?;
+
+        // protection information which is all the metadata isn't transferred if the controller inserts it
+        let inserted = protection.action && ns.metadata_size == format.size();
+        match metadata {
+            Some(metadata) => {
+                if ns.extended_lba || inserted {
+                    return Err(format!(
+                        "namespace {} transfers no separate metadata with this protection",
+                        ns.id
+                    )
+                    .into());
+                }
+                if metadata.size as u64 != blocks * ns.metadata_size {
+                    return Err(format!(
+                        "metadata of {blocks} blocks has to be {} bytes",
+                        blocks * ns.metadata_size
+                    )
+                    .into());
+                }
+                if metadata.phys % 4 != 0 {
+                    return Err(Error::Misaligned {
+                        addr: metadata.phys,
+                    });
                 }
-            } else {
-                eprintln!("error: {entry:?}");
-                eprintln!("queue full");
-                return reqs;
             }
+            None if ns.extended_lba && inserted => {
+                return Err(format!(
+                    "namespace {} has extended LBAs, its buffers include the protection information",
+                    ns.id
+                )
+                .into());
+            }
+            None if !ns.extended_lba && !inserted => {
+                return Err(Error::MetadataRequired { ns_id: ns.id });
+            }
+            None => {}
+        }
+
+        let command = if write {
+            NvmeCommand::io_write
+        } else {
+            NvmeCommand::io_read
+        };
+        let metadata = metadata.map(|metadata| metadata.phys as u64);
+        self.submit_namespace_command(
+            self.id,
+            ns,
+            data,
+            lba,
+            Some((protection, metadata)),
+            command,
+        )
+        .ok_or_else(|| "queue full".into())
+    }
+
+    /// Checks that `data` can be transferred to or from the blocks of `ns` starting at `lba`
+    pub(crate) fn check_io(
+        &self,
+        ns: &NvmeNamespace,
+        data: &impl DmaSlice,
+        lba: u64,
+    ) -> Result<()> {
+        let blocks = check_buffer(ns, data, chunk_size(ns, self.max_transfer_size))?;
+        ns.check_range(lba, blocks)
+    }
+
+    /// Submits a read or write of `data`, which has to pass `check_io`.
+    /// Returns `None` if the queue can't take all commands of the request.
+    pub(crate) fn submit_namespace_io(
+        &mut self,
+        sq_id: u16,
+        ns: &NvmeNamespace,
+        data: &impl DmaSlice,
+        lba: u64,
+        write: bool,
+    ) -> Option<NvmeRequest> {
+        let command = if write {
+            NvmeCommand::io_write
+        } else {
+            NvmeCommand::io_read
+        };
+        self.submit_namespace_command(sq_id, ns, data, lba, None, command)
+    }
+
+    /// Submits the commands built by `command` for the chunks of `data`, as one request.
+    /// The commands get `protection` and the metadata pointer into their part of the metadata buffer,
+    /// or without the protection the controller inserts that of `ns`, if any.
+    fn submit_namespace_command(
+        &mut self,
+        sq_id: u16,
+        ns: &NvmeNamespace,
+        data: &impl DmaSlice,
+        mut lba: u64,
+        protection: Option<(Protection, Option<u64>)>,
+        command: impl Fn(u16, u32, u64, u16, u64, u64) -> NvmeCommand,
+    ) -> Option<NvmeRequest> {
+        let start = lba;
+        let sq = self.sq_index(sq_id)?;
+        let chunk_size = chunk_size(ns, self.max_transfer_size);
+        let commands = data.chunks(chunk_size).count();
+        let queue = &self.sub_queues[sq];
+        if commands == 0
+            || commands > queue.c_ids.available()
+            || commands > queue.queue.free_slots()
+        {
+            return None;
+        }
+
+        let mut request = None;
+        for chunk in data.chunks(chunk_size) {
+            let queue = &mut self.sub_queues[sq];
+            let bytes = chunk.slice.len() as u64;
+            let blocks = bytes / ns.block_size;
+            let c_id = queue.c_ids.allocate(request)?;
+            request.get_or_insert(c_id);
+            let [ptr0, ptr1] = queue.prp_entries(c_id, chunk.phys_addr as u64, bytes);
+
+            let entry = command(c_id, ns.id, lba, (blocks - 1) as u16, ptr0, ptr1);
+            let entry = match protection {
+                Some((protection, metadata)) => {
+                    let pi_type = ns.protection.map_or(PiType::Type3, |format| format.pi_type);
+                    let entry = entry
+                        .with_protection(protection.advance(pi_type, lba - start), ns.protection);
+                    metadata.map_or(entry, |md_ptr| {
+                        entry.with_metadata(md_ptr + (lba - start) * ns.metadata_size)
+                    })
+                }
+                None => entry.with_protection(ns.inserted_protection(lba), ns.protection),
+            };
+            self.submit_command(sq, entry);
 
             lba += blocks;
-            reqs += 1;
         }
-        reqs
+
+        self.sub_queues[sq].ring_doorbell();
+        request.map(|c_id| NvmeRequest { sq_id, c_id })
     }
 
-    // TODO: maybe return result
-    ///
+    /// Submits a write as `submit_io` with Force Unit Access, so it only completes once the data is in non-volatile media
+    /// # Errors
+    /// Returns an error if `data` isn't made up of whole blocks of `ns` or the queue can't take all commands of the request
+    pub fn submit_write_fua(
+        &mut self,
+        ns: &NvmeNamespace,
+        data: &impl DmaSlice,
+        lba: u64,
+    ) -> Result<NvmeRequest> {
+        self.check_io(ns, data, lba)?;
+        self.submit_namespace_command(
+            self.id,
+            ns,
+            data,
+            lba,
+            None,
+            |c_id, ns_id, lba, blocks_1, ptr0, ptr1| {
+                NvmeCommand::io_write(c_id, ns_id, lba, blocks_1, ptr0, ptr1).with_fua()
+            },
+        )
+        .ok_or_else(|| "queue full".into())
+    }
+
+    /// Submits a Flush of `ns`, committing the data in the volatile write cache to non-volatile media.
+    /// Returns `None` without submitting anything if the controller has no volatile write cache.
+    /// # Errors
+    /// Returns an error if the queue is full
+    pub fn flush(&mut self, ns: &NvmeNamespace) -> Result<Option<NvmeRequest>> {
+        if !self.volatile_write_cache {
+            return Ok(None);
+        }
+        self.submit_blocks(0, 1, 1, |_, c_id, _, _| NvmeCommand::flush(c_id, ns.id))
+            .map(Some)
+    }
+
+    /// Submits a compare of `data` with the blocks starting at `lba`, the request fails with a compare failure if they differ
+    /// # Errors
+    /// Returns an error if the controller doesn't support Compare, `data` isn't made up of whole blocks of `ns` or the queue can't take the request
+    pub fn submit_compare(
+        &mut self,
+        ns: &NvmeNamespace,
+        data: &impl DmaSlice,
+        lba: u64,
+    ) -> Result<NvmeRequest> {
+        if self.oncs & ONCS_COMPARE == 0 {
+            return Err("controller does not support Compare".into());
+        }
+        self.check_io(ns, data, lba)?;
+        self.submit_namespace_command(self.id, ns, data, lba, None, NvmeCommand::io_compare)
+            .ok_or_else(|| "queue full".into())
+    }
+
+    /// Submits a fused Compare and Write, which atomically writes `write` to the blocks starting at `lba` if they hold `compare`.
+    /// Both commands complete as one request, which fails with a compare failure if the data differed.
+    /// Failed fused commands are never retried, as the pair has to be resubmitted together.
+    /// # Errors
+    /// Returns an error if the controller doesn't support fused Compare and Write, the buffers differ in size,
+    /// aren't made up of whole blocks of `ns` or exceed the maximum transfer size, or the queue is full
+    pub fn submit_compare_and_write(
+        &mut self,
+        ns: &NvmeNamespace,
+        compare: &impl DmaSlice,
+        write: &impl DmaSlice,
+        lba: u64,
+    ) -> Result<NvmeRequest> {
+        if self.fuses & FUSES_COMPARE_AND_WRITE == 0 {
+            return Err("controller does not support fused Compare and Write".into());
+        }
+        self.check_io(ns, compare, lba)?;
+        self.check_io(ns, write, lba)?;
+        let chunk_size = chunk_size(ns, self.max_transfer_size);
+        let mut compare = compare.chunks(chunk_size);
+        let mut write = write.chunks(chunk_size);
+        let (Some(compare), None, Some(write), None) =
+            (compare.next(), compare.next(), write.next(), write.next())
+        else {
+            return Err("compare and write must each fit into a single command".into());
+        };
+        if compare.slice.len() != write.slice.len() {
+            return Err("compare and write buffers differ in size".into());
+        }
+
+        let queue = &mut self.sub_queues[0];
+        if queue.c_ids.available() < 2 || queue.queue.free_slots() < 2 {
+            return Err("queue full".into());
+        }
+        let bytes = write.slice.len() as u64;
+        let blocks = bytes / ns.block_size;
+
+        let compare_id = queue.c_ids.allocate(None).ok_or("queue full")?;
+        let [ptr0, ptr1] = queue.prp_entries(compare_id, compare.phys_addr as u64, bytes);
+        let first =
+            NvmeCommand::io_compare(compare_id, ns.id, lba, (blocks - 1) as u16, ptr0, ptr1)
+                .with_protection(ns.inserted_protection(lba), ns.protection)
+                .with_fuse(NvmeCommand::FUSE_FIRST);
+
+        let write_id = queue.c_ids.allocate(Some(compare_id)).ok_or("queue full")?;
+        let [ptr0, ptr1] = queue.prp_entries(write_id, write.phys_addr as u64, bytes);
+        let second = NvmeCommand::io_write(write_id, ns.id, lba, (blocks - 1) as u16, ptr0, ptr1)
+            .with_protection(ns.inserted_protection(lba), ns.protection)
+            .with_fuse(NvmeCommand::FUSE_SECOND);
+
+        // the commands of a fused operation have to be adjacent in the submission queue
+        self.submit_command(0, first);
+        self.submit_command(0, second);
+        self.sub_queues[0].ring_doorbell();
+        Ok(NvmeRequest {
+            sq_id: self.id,
+            c_id: compare_id,
+        })
+    }
+
+    /// Submits a Dataset Management command for `ranges` of `ns`, e.g. to deallocate them with `DsmAttributes::DEALLOCATE`
+    /// # Errors
+    /// Returns an error if the controller doesn't support Dataset Management, the ranges are invalid or exceed `ns`, or the queue is full
+    pub fn submit_dataset_management(
+        &mut self,
+        ns: &NvmeNamespace,
+        ranges: &[DsmRange],
+        attributes: DsmAttributes,
+    ) -> Result<NvmeRequest> {
+        let max_ranges = self.sub_queues[0]
+            .list_entries::<DsmRange>()
+            .min(DsmRange::MAX_RANGES);
+        check_dataset_management(self.oncs, ns, ranges, max_ranges)?;
+        let queue = &mut self.sub_queues[0];
+        if queue.queue.is_full() {
+            return Err("queue full".into());
+        }
+        let c_id = queue.c_ids.allocate(None).ok_or("queue full")?;
+
+        // the range descriptors are placed in the list of the command
+        queue.list::<DsmRange>(c_id)[..ranges.len()].copy_from_slice(ranges);
+        let entry = NvmeCommand::dataset_management(
+            c_id,
+            ns.id,
+            queue.list_addr(c_id),
+            ranges.len() as u16,
+            attributes,
+        );
+
+        self.submit_command(0, entry);
+        self.sub_queues[0].ring_doorbell();
+        Ok(NvmeRequest {
+            sq_id: self.id,
+            c_id,
+        })
+    }
+
+    /// Submits the zeroing of `blocks` blocks of `ns` starting at `lba`, split into commands of at most the Write Zeroes size limit.
+    /// With `deallocate` the controller may deallocate the blocks instead, they read as zeroes either way.
+    /// Controllers without Write Zeroes get the blocks written from a shared zeroed buffer instead.
+    /// # Errors
+    /// Returns an error if `blocks` is 0, the blocks exceed `ns` or the queue can't take all commands of the request
+    pub fn write_zeroes(
+        &mut self,
+        ns: &NvmeNamespace,
+        lba: u64,
+        blocks: u64,
+        deallocate: bool,
+    ) -> Result<NvmeRequest> {
+        ns.check_range(lba, blocks)?;
+        if self.oncs & ONCS_WRITE_ZEROES != 0 {
+            let max_blocks = limit_blocks(self.write_zeroes_limit, ns.block_size);
+            return self.submit_blocks(lba, blocks, max_blocks, |_, c_id, lba, blocks_1| {
+                NvmeCommand::write_zeroes(c_id, ns.id, lba, blocks_1, deallocate)
+                    .with_protection(ns.inserted_protection(lba), ns.protection)
+            });
+        }
+
+        let zeroes = Arc::clone(self.zeroes.as_ref().ok_or("no zeroed buffer available")?);
+        let max_blocks = zeroes.size.min(self.max_transfer_size) as u64 / ns.block_size;
+        self.submit_blocks(lba, blocks, max_blocks, |queue, c_id, lba, blocks_1| {
+            let bytes = (u64::from(blocks_1) + 1) * ns.block_size;
+            let [ptr0, ptr1] = queue.prp_entries(c_id, zeroes.phys as u64, bytes);
+            NvmeCommand::io_write(c_id, ns.id, lba, blocks_1, ptr0, ptr1)
+                .with_protection(ns.inserted_protection(lba), ns.protection)
+        })
+    }
+
+    /// Submits a Verify of `blocks` blocks of `ns` starting at `lba`, which checks the integrity of the stored data without transferring it.
+    /// The request fails with the media error of the first unreadable block.
+    /// # Errors
+    /// Returns an error if the controller doesn't support Verify, `blocks` is 0, the blocks exceed `ns` or the queue can't take all commands of the request
+    pub fn submit_verify(
+        &mut self,
+        ns: &NvmeNamespace,
+        lba: u64,
+        blocks: u64,
+    ) -> Result<NvmeRequest> {
+        if self.oncs & ONCS_VERIFY == 0 {
+            return Err("controller does not support Verify".into());
+        }
+        ns.check_range(lba, blocks)?;
+        let max_blocks = limit_blocks(self.verify_limit, ns.block_size);
+        self.submit_blocks(lba, blocks, max_blocks, |_, c_id, lba, blocks_1| {
+            NvmeCommand::verify(c_id, ns.id, lba, blocks_1)
+        })
+    }
+
+    /// Submits a Write Uncorrectable of `blocks` blocks of `ns` starting at `lba`, marking them invalid so reads of them fail until they are written again.
+    /// Meant to inject media errors in test environments.
+    /// # Errors
+    /// Returns an error if the controller doesn't support Write Uncorrectable, `blocks` is 0, the blocks exceed `ns` or the queue can't take all commands of the request
+    pub fn submit_write_uncorrectable(
+        &mut self,
+        ns: &NvmeNamespace,
+        lba: u64,
+        blocks: u64,
+    ) -> Result<NvmeRequest> {
+        if self.oncs & ONCS_WRITE_UNCORRECTABLE == 0 {
+            return Err("controller does not support Write Uncorrectable".into());
+        }
+        ns.check_range(lba, blocks)?;
+        let max_blocks = limit_blocks(self.write_uncorrectable_limit, ns.block_size);
+        self.submit_blocks(lba, blocks, max_blocks, |_, c_id, lba, blocks_1| {
+            NvmeCommand::write_uncorrectable(c_id, ns.id, lba, blocks_1)
+        })
+    }
+
+    /// Submits a Copy of the blocks of `ranges` of `ns`, in order, to the blocks starting at `dest_lba`.
+    /// The data is moved by the controller without being transferred to the host.
+    /// # Errors
+    /// Returns an error if the controller doesn't support Copy, the ranges exceed the copy limits or the blocks of `ns`, or the queue is full
+    pub fn submit_copy(
+        &mut self,
+        ns: &NvmeNamespace,
+        ranges: &[CopyRange],
+        dest_lba: u64,
+    ) -> Result<NvmeRequest> {
+        let list_entries = self.sub_queues[0].list_entries::<CopyRange>();
+        check_copy(self.oncs, ns, ranges, dest_lba, list_entries)?;
+        let queue = &mut self.sub_queues[0];
+        if queue.queue.is_full() {
+            return Err("queue full".into());
+        }
+        let c_id = queue.c_ids.allocate(None).ok_or("queue full")?;
+
+        queue.list::<CopyRange>(c_id)[..ranges.len()].copy_from_slice(ranges);
+        let entry = NvmeCommand::copy(
+            c_id,
+            ns.id,
+            queue.list_addr(c_id),
+            ranges.len() as u16,
+            dest_lba,
+        );
+
+        self.submit_command(0, entry);
+        self.sub_queues[0].ring_doorbell();
+        Ok(NvmeRequest {
+            sq_id: self.id,
+            c_id,
+        })
+    }
+
+    /// Submits commands covering `blocks` blocks starting at `lba` to the primary submission queue as one request,
+    /// each command built by `command` from its command id, LBA and 0's based block count of at most `max_blocks`
+    fn submit_blocks(
+        &mut self,
+        mut lba: u64,
+        blocks: u64,
+        max_blocks: u64,
+        mut command: impl FnMut(&mut IoSubmissionQueue, u16, u64, u16) -> NvmeCommand,
+    ) -> Result<NvmeRequest> {
+        let max_blocks = max_blocks.clamp(1, MAX_COMMAND_BLOCKS);
+        let commands = blocks.div_ceil(max_blocks) as usize;
+        let queue = &self.sub_queues[0];
+        if commands == 0 {
+            return Err("request covers no blocks".into());
+        }
+        if commands > queue.c_ids.available() || commands > queue.queue.free_slots() {
+            return Err(format!("queue can't take the {commands} commands of the request").into());
+        }
+
+        let end = lba + blocks;
+        let mut request = None;
+        while lba < end {
+            let queue = &mut self.sub_queues[0];
+            let blocks = (end - lba).min(max_blocks);
+            let c_id = queue.c_ids.allocate(request).ok_or("queue full")?;
+            request.get_or_insert(c_id);
+            let entry = command(queue, c_id, lba, (blocks - 1) as u16);
+            self.submit_command(0, entry);
+            lba += blocks;
+        }
+
+        self.sub_queues[0].ring_doorbell();
+        let c_id = request.ok_or("request covers no blocks")?;
+        Ok(NvmeRequest {
+            sq_id: self.id,
+            c_id,
+        })
+    }
+
+    fn sq_index(&self, sq_id: u16) -> Option<usize> {
+        self.sub_queues.iter().position(|queue| queue.id == sq_id)
+    }
+
+    /// Ids of the submission queues attached to the completion queue, starting with the one created along with it
+    #[must_use]
+    pub fn sub_queue_ids(&self) -> Vec<u16> {
+        self.sub_queues.iter().map(|queue| queue.id).collect()
+    }
+
+    /// The submission queue created along with the completion queue
+    #[must_use]
+    pub fn sub_queue(&self) -> &SubmissionQueue {
+        &self.sub_queues[0].queue
+    }
+
+    /// Submits a single command transferring `segments` in order, using an SGL as data pointer.
+    /// Reads which are not a multiple of the block size of `ns` discard the rest of the last block, if the controller supports bit bucket descriptors.
+    /// # Errors
+    /// Returns an error if the controller doesn't support SGLs, the segments can't be described by a single SGL segment or the queue is full
+    pub fn submit_io_sgl(
+        &mut self,
+        ns: &NvmeNamespace,
+        segments: &[Dma<u8>],
+        lba: u64,
+        write: bool,
+    ) -> Result<NvmeRequest> {
+        if self.sgls & SGLS_SUPPORT_MASK == 0 {
+            return Err("controller does not support SGLs".into());
+        }
+        let dword_aligned = self.sgls & SGLS_SUPPORT_MASK == SGLS_DWORD_ALIGNED;
+
+        let bytes = segments.iter().map(|segment| segment.size).sum::<usize>() as u64;
+        if bytes == 0 || bytes > self.max_transfer_size as u64 {
+            return Err(format!("invalid SGL transfer size {bytes}").into());
+        }
+        if dword_aligned
+            && segments
+                .iter()
+                .any(|segment| segment.phys % 4 != 0 || segment.size % 4 != 0)
+        {
+            return Err("controller requires dword aligned SGL data blocks".into());
+        }
+
+        ns.check_metadata()?;
+        if ns.block_size == 0 {
+            return Err(format!("block size of namespace {} is not supported", ns.id).into());
+        }
+        let blocks = bytes.div_ceil(ns.block_size);
+        let padding = blocks * ns.block_size - bytes;
+        if padding != 0 && (write || self.sgls & SGLS_BIT_BUCKET == 0) {
+            return Err(Error::BlockSize {
+                len: bytes as usize,
+                block_size: ns.block_size,
+            });
+        }
+        ns.check_range(lba, blocks)?;
+
+        let descriptors = segments.len() + usize::from(padding != 0);
+        let queue = &mut self.sub_queues[0];
+        if descriptors > queue.list_entries::<SglDescriptor>() {
+            return Err(format!("too many SGL descriptors: {descriptors}").into());
+        }
+        if queue.queue.is_full() {
+            return Err("queue full".into());
+        }
+        let c_id = queue.c_ids.allocate(None).ok_or("queue full")?;
+
+        let sgl1 = if descriptors == 1 {
+            SglDescriptor::data_block(segments[0].phys as u64, segments[0].size as u32)
+        } else {
+            let list = queue.list::<SglDescriptor>(c_id);
+            for (entry, segment) in list.iter_mut().zip(segments) {
+                *entry = SglDescriptor::data_block(segment.phys as u64, segment.size as u32);
+            }
+            if padding != 0 {
+                list[segments.len()] = SglDescriptor::bit_bucket(padding as u32);
+            }
+            SglDescriptor::last_segment(queue.list_addr(c_id), descriptors as u32)
+        };
+
+        let entry = if write {
+            NvmeCommand::io_write(c_id, ns.id, lba, (blocks - 1) as u16, 0, 0)
+        } else {
+            NvmeCommand::io_read(c_id, ns.id, lba, (blocks - 1) as u16, 0, 0)
+        }
+        .with_protection(ns.inserted_protection(lba), ns.protection)
+        .with_sgl(sgl1);
+
+        self.submit_command(0, entry);
+        self.sub_queues[0].ring_doorbell();
+        Ok(NvmeRequest {
+            sq_id: self.id,
+            c_id,
+        })
+    }
+
+    /// Places `entry` in the submission queue with index `sq`, without ringing its doorbell
+    fn submit_command(&mut self, sq: usize, entry: NvmeCommand) {
+        if let Some(timer) = self.poll_timer.as_mut() {
+            timer.arm();
+        }
+        let queue = &mut self.sub_queues[sq];
+        queue.c_ids.submit(entry);
+        queue.queue.submit(entry);
+        self.stats.submissions += 1;
+    }
+
+    /// Resubmits the commands whose retry delay passed
+    fn resubmit_retries(&mut self) {
+        if self.pending_retries.is_empty() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut submitted = vec![false; self.sub_queues.len()];
+        let mut i = 0;
+        while i < self.pending_retries.len() {
+            let (due, sq, c_id) = self.pending_retries[i];
+            if due > now || self.sub_queues[sq].queue.is_full() {
+                i += 1;
+                continue;
+            }
+            self.pending_retries.swap_remove(i);
+            if let Some((entry, _)) = self.sub_queues[sq].c_ids.command(c_id) {
+                self.submit_command(sq, entry);
+                submitted[sq] = true;
+            }
+        }
+
+        for (queue, submitted) in self.sub_queues.iter().zip(submitted) {
+            if submitted {
+                queue.ring_doorbell();
+            }
+        }
+    }
+
+    /// Spins until `n` requests completed, returns them in the order they completed
+    /// # Errors
+    /// Returns `Error::Timeout` if a command is outstanding for longer than the command timeout.
+    /// Requests completed until then are returned by the following polls,
+    /// the expired commands can be aborted with `NvmeDevice::abort_expired`.
     /// # Panics
-    pub fn complete_io(&mut self, n: usize) -> Option<u16> {
+    pub fn complete_io(&mut self, n: usize) -> Result<Vec<NvmeCompletedRequest>> {
         assert!(n > 0);
-        let (tail, c_entry, _) = self.comp_queue.complete_n(n);
-        unsafe {
-            std::ptr::write_volatile(self.comp_queue.doorbell as *mut u32, tail as u32);
-        }
-        self.sub_queue.head = c_entry.sq_head as usize;
-        let status = c_entry.status >> 1;
-        if status != 0 {
-            eprintln!(
-                "COMPLETE_IO Status: 0x{:x}, Status Code 0x{:x}, Status Code Type: 0x{:x}",
-                status,
-                status & 0xFF,
-                (status >> 8) & 0x7
-            );
-            eprintln!("{c_entry:?}");
-            return None;
+        let mut completed: Vec<_> = self.ready.drain(..n.min(self.ready.len())).collect();
+        let wait_start = Instant::now();
+        let mut deadline = None;
+        let mut spins = 0u32;
+        while completed.len() < n {
+            self.resubmit_retries();
+            if let Some((tail, c_entry, _)) = self.comp_queue.complete() {
+                if let Some(request) = self.complete_entry(tail, c_entry) {
+                    completed.push(request);
+                }
+                continue;
+            }
+
+            spins = spins.wrapping_add(1);
+            if spins.is_multiple_of(TIMEOUT_CHECK_SPINS) {
+                if let Err(e) = self.check_timeout(&mut deadline) {
+                    self.ready.extend(completed);
+                    return Err(e);
+                }
+            }
+            self.idle(wait_start);
         }
-        Some(c_entry.sq_head)
+        Ok(completed)
     }
 
-    pub fn quick_poll(&mut self) -> Option<()> {
-        if let Some((tail, c_entry, _)) = self.comp_queue.complete() {
-            unsafe {
-                std::ptr::write_volatile(self.comp_queue.doorbell as *mut u32, tail as u32);
+    /// Waits in between two polls of an empty completion queue, according to the polling strategy
+    fn idle(&self, wait_start: Instant) {
+        let Some(expected) = self
+            .latency_estimate
+            .filter(|_| self.poll_strategy != PollStrategy::Spin)
+        else {
+            spin_loop();
+            return;
+        };
+
+        let waited = wait_start.elapsed();
+        match self.poll_strategy {
+            PollStrategy::SpinThenSleep if waited < expected / 2 => {
+                std::thread::sleep((expected / 2).saturating_sub(waited));
             }
-            self.sub_queue.head = c_entry.sq_head as usize;
-            let status = c_entry.status >> 1;
-            let comp_status = c_entry.status;
-            if status != 0 {
-                eprintln!(
-                    "QUICK_POLL Status: 0x{:x}, Status Code 0x{:x}, Status Code Type: 0x{:x}, ---------------> {}",
-                    status,
-                    status & 0xFF,
-                    (status >> 8) & 0x7,
-                    Self::u16_to_variable_bit_chunks(comp_status, &vec![1,1,2,3,8,1])
-                );
-                eprintln!("{c_entry:?}");
+            _ if waited < expected => spin_loop(),
+            PollStrategy::SpinThenYield => std::thread::yield_now(),
+            PollStrategy::SpinThenSleep => std::thread::sleep(expected / 4),
+            PollStrategy::Spin => spin_loop(),
+        }
+    }
+
+    /// Expected completion time of a command, as learned by polling strategies other than `PollStrategy::Spin`
+    #[must_use]
+    pub const fn latency_estimate(&self) -> Option<Duration> {
+        self.latency_estimate
+    }
+
+    /// Adds the latency of command `c_id` of the submission queue with index `sq`, which just completed, to the moving average
+    fn record_latency(&mut self, sq: usize, c_id: u16) {
+        let Some(submitted) = self.sub_queues[sq].c_ids.submitted(c_id) else {
+            return;
+        };
+        let latency = submitted.elapsed();
+        self.latency_estimate = Some(self.latency_estimate.map_or(latency, |estimate| {
+            (estimate * (LATENCY_WEIGHT - 1) + latency) / LATENCY_WEIGHT
+        }));
+    }
+
+    /// Fails if the oldest command at the controller expired.
+    /// `deadline` caches the expiry of the oldest command, so the commands are only scanned once it passed.
+    fn check_timeout(&self, deadline: &mut Option<Instant>) -> Result<()> {
+        let Some(timeout) = self.command_timeout else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| now <= deadline) {
+            return Ok(());
+        }
+
+        // the command the cached deadline belonged to may have completed meanwhile
+        *deadline = self
+            .sub_queues
+            .iter()
+            .filter_map(|queue| queue.c_ids.oldest_submission())
+            .min()
+            .map(|submitted| submitted + timeout);
+        if deadline.is_some_and(|deadline| now > deadline) {
+            return Err(Error::Timeout(format!(
+                "command outstanding for longer than {timeout:?} on queue {}",
+                self.id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the next finished request, if any.
+    /// Doesn't check for timed out commands, see `NvmeDevice::abort_expired`.
+    pub fn quick_poll(&mut self) -> Option<NvmeCompletedRequest> {
+        if let Some(completed) = self.ready.pop_front() {
+            return Some(completed);
+        }
+        self.resubmit_retries();
+        while let Some((tail, c_entry, _)) = self.comp_queue.complete() {
+            if let Some(completed) = self.complete_entry(tail, c_entry) {
+                return Some(completed);
             }
-            return Some(());
         }
         None
     }
 
-    fn u16_to_variable_bit_chunks(n: u16, chunk_sizes: &Vec<usize>) -> String {
-        let binary_string = format!("{n:016b}");
-        let mut chunks = Vec::new();
-        let mut pos = 0;
-        for &size in chunk_sizes {
-            let end = if pos + size > 16 { 16 } else { pos + size };
-            let chunk: String = binary_string[pos..end].to_string();
-            chunks.push(chunk);
-            pos = end;
-            if pos >= 16 {
-                break;
+    /// Blocks until the next request finished, sleeping on the interrupt of the completion queue instead of spinning.
+    /// Requires interrupts to be enabled by `NvmeDevice::enable_interrupts` before the queue pair was created.
+    /// # Errors
+    /// Returns an error if interrupts are not enabled for the queue pair, no commands are outstanding,
+    /// waiting on the interrupt failed or `Error::Timeout` if a command expired as in `complete_io`.
+    pub fn wait_for_completion(&mut self) -> Result<NvmeCompletedRequest> {
+        let interrupt = self
+            .interrupt
+            .ok_or("interrupts are not enabled for this queue pair")?;
+
+        let mut deadline = None;
+        loop {
+            if let Some(completed) = self.quick_poll() {
+                return Ok(completed);
             }
+            if self.outstanding() == 0 {
+                return Err("no outstanding commands".into());
+            }
+            self.check_timeout(&mut deadline)?;
+
+            // wake up for pending retries and command timeouts as well
+            let wake = self
+                .pending_retries
+                .iter()
+                .map(|&(due, _, _)| due)
+                .chain(deadline)
+                .min();
+            notify::wait(
+                interrupt,
+                wake.map(|wake| wake.saturating_duration_since(Instant::now())),
+            )?;
         }
-        chunks.join(" ")
     }
 
-    ///
+    /// File descriptor which becomes readable when completions may be pending, to wait for the queue pair with epoll and alike.
+    /// This is the MSI-X eventfd of the queue if interrupts are enabled, otherwise the timer enabled by `enable_poll_timer`.
+    /// Once it is readable, `poll_completions` collects the finished requests.
+    #[must_use]
+    pub fn completion_fd(&self) -> Option<RawFd> {
+        self.interrupt
+            .or_else(|| self.poll_timer.as_ref().map(AsRawFd::as_raw_fd))
+    }
+
+    /// Enables a timerfd as completion fd of a queue pair without interrupts.
+    /// It fires every `interval` while commands are outstanding.
     /// # Errors
-    pub fn quick_poll_result(&mut self) -> Result<Option<()>> {
-        if let Some((tail, c_entry, _)) = self.comp_queue.complete() {
-            unsafe {
-                std::ptr::write_volatile(self.comp_queue.doorbell as *mut u32, tail as u32);
-            }
-            self.sub_queue.head = c_entry.sq_head as usize;
-            let status = c_entry.status >> 1;
-            if status != 0 {
-                let error_message = format!(
-                    "QUICK_POLL Status: 0x{:x}, Status Code 0x{:x}, Status Code Type: 0x{:x}\n{:?}",
-                    status,
-                    status & 0xFF,
-                    (status >> 8) & 0x7,
-                    c_entry
-                );
-                eprintln!("{error_message}");
-                return Err(format!("Error: {error_message}",).into());
+    /// Returns an error if interrupts are enabled for this queue pair or the timer can't be created
+    pub fn enable_poll_timer(&mut self, interval: Duration) -> Result<RawFd> {
+        if self.interrupt.is_some() {
+            return Err("queue pair signals completions by interrupt already".into());
+        }
+        let mut timer = PollTimer::new(interval)?;
+        if self.outstanding() > 0 {
+            timer.arm();
+        }
+        let fd = timer.as_raw_fd();
+        self.poll_timer = Some(timer);
+        Ok(fd)
+    }
+
+    /// Resets the completion fd and returns all finished requests, to be called once it became readable.
+    /// # Errors
+    /// Returns an error if resetting the completion fd failed
+    pub fn poll_completions(&mut self) -> Result<Vec<NvmeCompletedRequest>> {
+        // reset before polling, so completions arriving meanwhile signal the fd again
+        if let Some(fd) = self.completion_fd() {
+            notify::wait(fd, Some(Duration::ZERO))?;
+        }
+
+        let mut completed = Vec::new();
+        while let Some(request) = self.quick_poll() {
+            completed.push(request);
+        }
+        if self.outstanding() == 0 {
+            if let Some(timer) = self.poll_timer.as_mut() {
+                timer.disarm();
             }
-            return Ok(Some(()));
         }
-        Ok(None)
+        Ok(completed)
+    }
+
+    /// Amount of commands submitted but not yet completed
+    #[must_use]
+    pub fn outstanding(&self) -> usize {
+        self.sub_queues
+            .iter()
+            .map(|queue| queue.c_ids.in_flight())
+            .sum()
+    }
+
+    /// Consumes the completion `c_entry`, returns its request if this was the request's last command.
+    /// Commands failing with a retryable status are scheduled for resubmission according to the retry policy.
+    fn complete_entry(
+        &mut self,
+        head: usize,
+        c_entry: NvmeCompletion,
+    ) -> Option<NvmeCompletedRequest> {
+        unsafe {
+            std::ptr::write_volatile(self.comp_queue.doorbell as *mut u32, head as u32);
+        }
+        self.stats.completions += 1;
+        let sq = self.sq_index(c_entry.sq_id)?;
+        self.sub_queues[sq].queue.head = c_entry.sq_head as usize;
+        if self.poll_strategy != PollStrategy::Spin {
+            self.record_latency(sq, c_entry.c_id);
+        }
+
+        let queue = &mut self.sub_queues[sq];
+        let status = NvmeStatus::new(c_entry.status >> 1);
+        if !status.is_success() {
+            if let Some((entry, attempts)) = queue.c_ids.command(c_entry.c_id) {
+                let delay = self.retry_policy.retry_delay(status, attempts, self.crdt);
+                if let Some(delay) = delay.filter(|_| !entry.is_fused()) {
+                    queue.c_ids.requeue(c_entry.c_id);
+                    self.pending_retries
+                        .push((Instant::now() + delay, sq, c_entry.c_id));
+                    self.stats.retries += 1;
+                    return None;
+                }
+            }
+        }
+
+        queue
+            .c_ids
+            .complete(c_entry.c_id, c_entry.status >> 1)
+            .map(|(c_id, status)| NvmeCompletedRequest {
+                request: NvmeRequest {
+                    sq_id: queue.id,
+                    c_id,
+                },
+                sq_id: queue.id,
+                status: NvmeStatus::new(status),
+            })
+    }
+
+    /// Returns the next finished request, if any
+    /// # Errors
+    /// Returns `Error::Nvme` if the request failed
+    pub fn quick_poll_result(&mut self) -> Result<Option<NvmeRequest>> {
+        self.quick_poll().map_or(Ok(None), |completed| {
+            completed.result().map(|()| Some(completed.request))
+        })
     }
 }
 
@@ -256,6 +1440,37 @@ pub struct NvmeDevice {
     pub namespaces: HashMap<u32, NvmeNamespace>,
     pub stats: NvmeStats,
     q_id: u16,
+    // entries per I/O queue supported by the controller (CAP.MQES)
+    max_queue_len: usize,
+    max_transfer_size: usize,
+    sgls: u32,
+    // optional NVM commands and fused operations supported by the controller
+    oncs: u16,
+    fuses: u16,
+    // bytes a single Write Zeroes command may cover (WZSL), 0 if only limited by NLB
+    write_zeroes_limit: usize,
+    // bytes a single Verify (VSL) or Write Uncorrectable (WUSL) command may cover, 0 if only limited by NLB
+    verify_limit: usize,
+    write_uncorrectable_limit: usize,
+    // VWC of identify controller, flushes are only needed with a volatile write cache
+    volatile_write_cache: bool,
+    // CTRATT of identify controller, with ELBAS namespaces report their protection information format in extended LBA formats
+    ctratt: u32,
+    // zeroed buffer shared by the queue pairs to emulate Write Zeroes, allocated once needed
+    zeroes: Option<Arc<Dma<u8>>>,
+    crdt: [u16; 3],
+    pub retry_policy: RetryPolicy,
+    /// Maximum time a command may be outstanding at the controller, `None` to wait forever.
+    /// Inherited by queue pairs created afterwards.
+    pub command_timeout: Option<Duration>,
+    // eventfds of the MSI-X vectors, indexed by vector, empty if interrupts are disabled
+    interrupts: Vec<OwnedFd>,
+    // weighted round robin with urgent priority class enabled in CC.AMS
+    weighted_round_robin: bool,
+    cmb: Option<ControllerMemoryBuffer>,
+    // place submission queues created afterwards in the CMB
+    cmb_submission_queues: bool,
+    pmr: Option<PersistentMemoryRegion>,
     pub allocator: Box<MemoryAccess>,
 }
 
@@ -263,13 +1478,96 @@ pub struct NvmeDevice {
 pub struct NvmeNamespace {
     pub id: u32,
     pub blocks: u64,
+    /// Bytes each block takes up in data buffers, including its metadata with extended LBAs
     pub block_size: u64,
+    /// Bytes of metadata of each block (MS), 0 if the namespace has none
+    pub metadata_size: u64,
+    /// The metadata is transferred at the end of each block in the data buffer instead of in a separate buffer (FLBAS)
+    pub extended_lba: bool,
+    /// End-to-end data protection the namespace is formatted with, `None` if disabled or not supported
+    pub protection: Option<PiFormat>,
+    /// Maximum blocks of a single source range of a Copy (MSSRL), 0 if not limited
+    pub max_copy_range_blocks: u16,
+    /// Maximum blocks a Copy may cover in total (MCL), 0 if not limited
+    pub max_copy_blocks: u32,
+    /// Maximum source ranges of a Copy (MSRC + 1)
+    pub max_copy_ranges: u16,
+}
+
+impl NvmeNamespace {
+    /// Checks that `blocks` blocks starting at `lba` lie within the namespace
+    /// # Errors
+    /// Returns `Error::OutOfRange` if the blocks exceed the namespace
+    pub const fn check_range(&self, lba: u64, blocks: u64) -> Result<()> {
+        match lba.checked_add(blocks) {
+            Some(end) if end <= self.blocks => Ok(()),
+            _ => Err(Error::OutOfRange {
+                ns_id: self.id,
+                lba,
+                blocks,
+                capacity: self.blocks,
+            }),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct NvmeStats {
     pub completions: u64,
     pub submissions: u64,
+    pub retries: u64,
+}
+
+/// Resubmission of commands which failed with a retryable status
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Maximum attempts per command, including the first submission
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled with each further retry
+    pub backoff: Duration,
+    /// Wait at least the command retry delay (CRDT1-3) selected by the controller
+    pub respect_crd: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            backoff: Duration::from_micros(100),
+            respect_crd: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry commands
+    pub const NONE: Self = Self {
+        max_attempts: 1,
+        backoff: Duration::ZERO,
+        respect_crd: false,
+    };
+
+    /// Returns how long to wait before retrying a command that failed with `status` after `attempts` attempts, `None` if it must not be retried
+    #[must_use]
+    pub fn retry_delay(
+        &self,
+        status: NvmeStatus,
+        attempts: u32,
+        crdt: [u16; 3],
+    ) -> Option<Duration> {
+        if !status.is_retryable() || attempts >= self.max_attempts {
+            return None;
+        }
+
+        let backoff = self.backoff.saturating_mul(1 << (attempts - 1).min(16));
+        let crd = match status.crd {
+            1..=3 if self.respect_crd => {
+                Duration::from_millis(100 * u64::from(crdt[status.crd as usize - 1]))
+            }
+            _ => Duration::ZERO,
+        };
+        Some(backoff.max(crd))
+    }
 }
 
 // TODO
@@ -281,6 +1579,66 @@ static BUFFER_SIZE: AtomicUsize = AtomicUsize::new(PAGESIZE_4KIB);
 
 // currently fixed
 const PRP_LIST_SIZE: usize = PAGESIZE_4KIB;
+const PRP_LIST_ENTRIES: usize = PRP_LIST_SIZE / 8;
+
+// a single PRP list page plus PRP1 covers this much data, even if PRP1 is not page aligned
+const MAX_PRP_TRANSFER_SIZE: usize = PRP_LIST_ENTRIES * PAGESIZE_4KIB;
+
+// smallest list of a command, still room for 32 range or SGL descriptors
+const MIN_LIST_SIZE: usize = 512;
+
+// SGLS field of identify controller
+const SGLS_SUPPORT_MASK: u32 = 0b11;
+const SGLS_DWORD_ALIGNED: u32 = 0b10;
+const SGLS_BIT_BUCKET: u32 = 1 << 16;
+
+// ONCS and FUSES fields of identify controller
+const ONCS_COMPARE: u16 = 1 << 0;
+const ONCS_WRITE_UNCORRECTABLE: u16 = 1 << 1;
+const ONCS_DSM: u16 = 1 << 2;
+const ONCS_WRITE_ZEROES: u16 = 1 << 3;
+const ONCS_VERIFY: u16 = 1 << 7;
+const ONCS_COPY: u16 = 1 << 8;
+const FUSES_COMPARE_AND_WRITE: u16 = 1 << 0;
+const VWC_PRESENT: u8 = 1 << 0;
+const CTRATT_ELBAS: u32 = 1 << 15;
+const FLBAS_EXTENDED: u8 = 1 << 4;
+const DPS_PI_FIRST: u8 = 1 << 3;
+
+// NLB of a single command is a 16 bit, 0's based value
+const MAX_COMMAND_BLOCKS: u64 = 1 << 16;
+
+// the admin queue only ever holds a single command
+const ADMIN_QUEUE_LENGTH: usize = 64;
+// enough for a batch of 512 byte blocks covering the whole 2 MiB buffer
+const LEGACY_IO_QUEUE_LENGTH: usize = PAGESIZE_2MIB / 512 + 1;
+
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+// weight of the command latency history in the moving average, the latest latency counts 1 / LATENCY_WEIGHT
+const LATENCY_WEIGHT: u32 = 8;
+
+// idle polls of a queue pair between checks for expired commands
+const TIMEOUT_CHECK_SPINS: u32 = 1024;
+
+// weighted round robin with urgent priority class, supported bit of CAP.AMS and value of CC.AMS
+const CAP_AMS_WRR: u64 = 1 << 17;
+const CC_AMS_WRR: u32 = 1 << 11;
+
+// CAP.CMBS: CMBLOC and CMBSZ only read valid once CMBMSC.CRE is set
+const CAP_CMBS: u64 = 1 << 57;
+// capability registers enabled and controller memory space enabled bits of CMBMSC
+const CMBMSC_CRE: u64 = 1 << 0;
+const CMBMSC_CMSE: u64 = 1 << 1;
+
+// CAP.PMRS: the controller has a persistent memory region
+const CAP_PMRS: u64 = 1 << 56;
+const PMRCTL_EN: u32 = 1;
+const PMRSTS_NRDY: u32 = 1 << 8;
+
+const FEATURE_ARBITRATION: u8 = 0x01;
+const FEATURE_HOST_BEHAVIOR_SUPPORT: u8 = 0x16;
+const HOST_BEHAVIOR_SUPPORT_SIZE: usize = 512;
 
 #[allow(unused)]
 impl NvmeDevice {
@@ -297,37 +1655,54 @@ impl NvmeDevice {
         let buffer: Dma<u8> = allocator.allocate(BUFFER_SIZE.load(Ordering::Relaxed))?;
         let prp_list: Dma<[u64; 512]> = allocator.allocate(PRP_LIST_SIZE)?;
 
+        let cap = unsafe {
+            std::ptr::read_volatile((addr as usize + NvmeRegs64::CAP as usize) as *const u64)
+        };
+        // MQES is 0's based
+        let max_queue_len = (cap & 0xFFFF) as usize + 1;
+        let io_queue_len = max_queue_len.min(LEGACY_IO_QUEUE_LENGTH);
+
         let mut dev = Self {
             pci_addr: pci_addr.to_string(),
             addr,
-            dstrd: {
-                unsafe {
-                    ((std::ptr::read_volatile(
-                        (addr as usize + NvmeRegs64::CAP as usize) as *const u64,
-                    ) >> 32)
-                        & 0b1111) as u16
-                }
-            },
+            dstrd: ((cap >> 32) & 0b1111) as u16,
             len,
-            admin_sq: SubmissionQueue::new(&allocator, QUEUE_LENGTH, 0)?,
-            admin_cq: CompletionQueue::new(&allocator, QUEUE_LENGTH, 0)?,
-            io_sq: SubmissionQueue::new(&allocator, QUEUE_LENGTH, 0)?,
-            io_cq: CompletionQueue::new(&allocator, QUEUE_LENGTH, 0)?,
+            admin_sq: SubmissionQueue::new(&allocator, ADMIN_QUEUE_LENGTH, 0)?,
+            admin_cq: CompletionQueue::new(&allocator, ADMIN_QUEUE_LENGTH, 0)?,
+            io_sq: SubmissionQueue::new(&allocator, io_queue_len, 0)?,
+            io_cq: CompletionQueue::new(&allocator, io_queue_len, 0)?,
+            max_queue_len,
             buffer,
             prp_list,
             namespaces: HashMap::new(),
             stats: NvmeStats::default(),
             q_id: 1,
+            max_transfer_size: MAX_PRP_TRANSFER_SIZE,
+            sgls: 0,
+            oncs: 0,
+            fuses: 0,
+            write_zeroes_limit: 0,
+            verify_limit: 0,
+            write_uncorrectable_limit: 0,
+            volatile_write_cache: false,
+            ctratt: 0,
+            zeroes: None,
+            crdt: [0; 3],
+            retry_policy: RetryPolicy::default(),
+            command_timeout: Some(DEFAULT_COMMAND_TIMEOUT),
+            interrupts: Vec::new(),
+            weighted_round_robin: false,
+            cmb: None,
+            cmb_submission_queues: false,
+            pmr: None,
             allocator,
         };
 
-        for i in 1..512 {
-            dev.prp_list[i - 1] = (dev.buffer.phys + i * 4096) as u64;
+        for i in 0..512 {
+            dev.prp_list[i] = (dev.buffer.phys + i * 4096) as u64;
         }
 
-        let cap = dev.get_reg64(NvmeRegs64::CAP as u64);
-        let maximum_queue_size = (cap & 0xFFFF) as u16 + 1;
-        println!("Maximum Queue Size: {maximum_queue_size}");
+        println!("Maximum Queue Size: {max_queue_len}");
 
         println!("CAP: 0x{:x}", dev.get_reg64(NvmeRegs64::CAP as u64));
         println!("VS: 0x{:x}", dev.get_reg32(NvmeRegs32::VS as u32));
@@ -338,14 +1713,7 @@ impl NvmeDevice {
         dev.set_reg32(NvmeRegs32::CC as u32, ctrl_config);
 
         // Wait for not ready
-        loop {
-            let csts = dev.get_reg32(NvmeRegs32::CSTS as u32);
-            if csts & 1 == 1 {
-                spin_loop();
-            } else {
-                break;
-            }
-        }
+        dev.wait_ready(false)?;
 
         // Configure Admin Queues
         // Initialize the addresses of the admin completion/submission queues on the device
@@ -353,7 +1721,7 @@ impl NvmeDevice {
         dev.set_reg64(NvmeRegs64::ACQ as u32, dev.admin_cq.get_addr() as u64);
         dev.set_reg32(
             NvmeRegs32::AQA as u32,
-            (QUEUE_LENGTH as u32 - 1) << 16 | (QUEUE_LENGTH as u32 - 1),
+            (ADMIN_QUEUE_LENGTH as u32 - 1) << 16 | (ADMIN_QUEUE_LENGTH as u32 - 1),
         );
 
         // Configure other stuff
@@ -364,6 +1732,12 @@ impl NvmeDevice {
         // Set Completion (2^4 = 16 Bytes) and Submission Entry (2^6 = 64 Bytes) sizes
         cc |= (4 << 20) | (6 << 16);
 
+        // Select weighted round robin arbitration if CAP.AMS supports it
+        dev.weighted_round_robin = cap & CAP_AMS_WRR != 0;
+        if dev.weighted_round_robin {
+            cc |= CC_AMS_WRR;
+        }
+
         // Set Memory Page Size
         // let mpsmax = ((dev.get_reg64(NvmeRegs64::CAP as u64) >> 52) & 0xF) as u32;
         // cc |= (mpsmax << 7);
@@ -375,20 +1749,13 @@ impl NvmeDevice {
         dev.set_reg32(NvmeRegs32::CC as u32, ctrl_config);
 
         // wait for ready
-        loop {
-            let csts = dev.get_reg32(NvmeRegs32::CSTS as u32);
-            if csts & 1 == 0 {
-                spin_loop();
-            } else {
-                break;
-            }
-        }
+        dev.wait_ready(true)?;
 
         let q_id = dev.q_id;
         let addr = dev.io_cq.get_addr();
         println!("Requesting i/o completion queue");
         let comp = dev.submit_and_complete_admin(|c_id, _| {
-            NvmeCommand::create_io_completion_queue(c_id, q_id, addr, (QUEUE_LENGTH - 1) as u16)
+            NvmeCommand::create_io_completion_queue(c_id, q_id, addr, (io_queue_len - 1) as u16)
         })?;
         let addr = dev.io_sq.get_addr();
         println!("Requesting i/o submission queue");
@@ -397,13 +1764,222 @@ impl NvmeDevice {
                 c_id,
                 q_id,
                 addr,
-                (QUEUE_LENGTH - 1) as u16,
+                (io_queue_len - 1) as u16,
                 q_id,
             )
         })?;
         dev.q_id += 1;
 
-        Ok(dev)
+        // learn the data transfer limits and retry delays of the controller
+        dev.identify_controller()?;
+        dev.identify_controller_nvm();
+
+        dev.enable_command_retry_delays();
+
+        if dev.weighted_round_robin {
+            dev.set_arbitration_weights(ArbitrationWeights::default())?;
+        }
+
+        Ok(dev)
+    }
+
+    /// Spins until CSTS.RDY equals `ready`, bounded by the worst case time reported in CAP.TO
+    fn wait_ready(&self, ready: bool) -> Result<()> {
+        // CAP.TO is in 500 ms units
+        let to = (self.get_reg64(NvmeRegs64::CAP as u64) >> 24) & 0xFF;
+        let timeout = Duration::from_millis(500 * to);
+        let deadline = Instant::now() + timeout;
+
+        while (self.get_reg32(NvmeRegs32::CSTS as u32) & 1 == 1) != ready {
+            if Instant::now() > deadline {
+                return Err(Error::Timeout(format!(
+                    "controller did not {} within {timeout:?}",
+                    if ready { "become ready" } else { "shut down" }
+                )));
+            }
+            spin_loop();
+        }
+        Ok(())
+    }
+
+    /// The controller only reports command retry delays if the host enables ACRE
+    fn enable_command_retry_delays(&mut self) {
+        if self.crdt == [0; 3] {
+            return;
+        }
+
+        let data = &mut self.buffer[..HOST_BEHAVIOR_SUPPORT_SIZE];
+        data.fill(0);
+        // ACRE
+        data[0] = 1;
+        if self
+            .submit_and_complete_admin(|c_id, addr| {
+                NvmeCommand::set_features(c_id, addr, FEATURE_HOST_BEHAVIOR_SUPPORT, 0)
+            })
+            .is_err()
+        {
+            self.crdt = [0; 3];
+        }
+    }
+
+    /// Whether the controller arbitrates between submission queues by weighted round robin, so queue priorities take effect
+    #[must_use]
+    pub const fn weighted_round_robin(&self) -> bool {
+        self.weighted_round_robin
+    }
+
+    /// Sets the weights of the priority classes under weighted round robin arbitration
+    /// # Errors
+    /// Returns an error if the controller doesn't use weighted round robin arbitration or rejected the weights
+    pub fn set_arbitration_weights(&mut self, weights: ArbitrationWeights) -> Result<()> {
+        if !self.weighted_round_robin {
+            return Err("controller does not support weighted round robin arbitration".into());
+        }
+        self.submit_and_complete_admin(|c_id, addr| {
+            NvmeCommand::set_features(c_id, addr, FEATURE_ARBITRATION, weights.cdw11())
+        })?;
+        Ok(())
+    }
+
+    /// Switches queue pairs created afterwards to interrupt driven completions, see `NvmeQueuePair::wait_for_completion`.
+    /// The completion queue of each queue pair signals the MSI-X vector matching its queue id.
+    /// # Errors
+    /// Returns an error if the device is not bound to VFIO or MSI-X can't be enabled
+    pub fn enable_interrupts(&mut self) -> Result<()> {
+        if !self.interrupts.is_empty() {
+            return Ok(());
+        }
+        let MemoryAccess::Vfio(vfio) = self.allocator.as_ref() else {
+            return Err(Error::Vfio(
+                "interrupts are only supported with VFIO".to_string(),
+            ));
+        };
+
+        let vectors = vfio.msix_vectors()?;
+        if vectors == 0 {
+            return Err(Error::Vfio("device does not support MSI-X".to_string()));
+        }
+        self.interrupts = vfio.enable_msix(vectors)?;
+        Ok(())
+    }
+
+    /// Discovers the Controller Memory Buffer, maps the BAR it lives in and enables the controller to access it
+    /// # Errors
+    /// Returns an error if the controller has no CMB or its BAR can't be mapped
+    pub fn enable_cmb(&mut self) -> Result<()> {
+        if self.cmb.is_some() {
+            return Ok(());
+        }
+        let cmbs = self.get_reg64(NvmeRegs64::CAP as u64) & CAP_CMBS != 0;
+        if cmbs {
+            self.set_reg64(NvmeRegs64::CMBMSC as u32, CMBMSC_CRE);
+        }
+
+        let cmbsz = self.get_reg32(NvmeRegs32::CMBSZ as u32);
+        let cmbloc = self.get_reg32(NvmeRegs32::CMBLOC as u32);
+        if cmbsz == 0 {
+            return Err("controller has no controller memory buffer".into());
+        }
+
+        // size and offset are given in units of 4 KiB * 16^SZU
+        let unit = PAGESIZE_4KIB << (4 * ((cmbsz >> 8) & 0xF));
+        let size = (cmbsz >> 12) as usize * unit;
+        let offset = (cmbloc >> 12) as usize * unit;
+        let bar = cmbloc & 0b111;
+
+        let (bar_addr, bar_len) = self.allocator.map_bar(bar)?;
+        if offset + size > bar_len {
+            return Err(format!("controller memory buffer exceeds BAR {bar}").into());
+        }
+        let addr = read_bar_address(&self.pci_addr, bar)? + offset as u64;
+        if cmbs {
+            self.set_reg64(NvmeRegs64::CMBMSC as u32, addr | CMBMSC_CMSE | CMBMSC_CRE);
+        }
+
+        let virt = unsafe { bar_addr.add(offset) };
+        self.cmb = Some(ControllerMemoryBuffer::new(virt, addr, size, cmbsz));
+        Ok(())
+    }
+
+    /// The Controller Memory Buffer, if it has been enabled
+    #[must_use]
+    pub const fn cmb(&self) -> Option<&ControllerMemoryBuffer> {
+        self.cmb.as_ref()
+    }
+
+    /// Places submission queues created afterwards in the Controller Memory Buffer,
+    /// so the controller fetches commands without a round trip to host memory
+    /// # Errors
+    /// Returns an error if the CMB is not enabled or doesn't support submission queues
+    pub fn set_cmb_submission_queues(&mut self, enabled: bool) -> Result<()> {
+        if enabled
+            && !self
+                .cmb
+                .as_ref()
+                .is_some_and(ControllerMemoryBuffer::supports_submission_queues)
+        {
+            return Err("controller memory buffer does not support submission queues".into());
+        }
+        self.cmb_submission_queues = enabled;
+        Ok(())
+    }
+
+    /// Enables the Persistent Memory Region and maps the BAR it occupies
+    /// # Errors
+    /// Returns an error if the controller has no PMR, it doesn't become ready or reports an unhealthy status
+    pub fn enable_pmr(&mut self) -> Result<()> {
+        if self.pmr.is_some() {
+            return Ok(());
+        }
+        if self.get_reg64(NvmeRegs64::CAP as u64) & CAP_PMRS == 0 {
+            return Err("controller has no persistent memory region".into());
+        }
+
+        let caps = PmrCapabilities::new(self.get_reg32(NvmeRegs32::PMRCAP as u32));
+        let (virt, size) = self.allocator.map_bar(caps.bar)?;
+
+        self.set_reg32(NvmeRegs32::PMRCTL as u32, PMRCTL_EN);
+        let timeout = caps.ready_timeout;
+        let deadline = Instant::now() + timeout;
+        let pmrsts = loop {
+            let pmrsts = self.get_reg32(NvmeRegs32::PMRSTS as u32);
+            if pmrsts & PMRSTS_NRDY == 0 {
+                break pmrsts;
+            }
+            if Instant::now() > deadline {
+                return Err(Error::Timeout(format!(
+                    "persistent memory region did not become ready within {timeout:?}"
+                )));
+            }
+            spin_loop();
+        };
+        // HSTS
+        match (pmrsts >> 9) & 0b111 {
+            0 => {}
+            hsts => {
+                return Err(format!("persistent memory region is unhealthy, HSTS {hsts}").into())
+            }
+        }
+
+        let pmrsts = (self.addr as usize + NvmeRegs32::PMRSTS as usize) as *const u32;
+        self.pmr = Some(PersistentMemoryRegion::new(
+            virt,
+            size,
+            pmrsts,
+            caps.barriers,
+        ));
+        Ok(())
+    }
+
+    /// The Persistent Memory Region, if it has been enabled
+    #[must_use]
+    pub const fn pmr(&self) -> Option<&PersistentMemoryRegion> {
+        self.pmr.as_ref()
+    }
+
+    /// The Persistent Memory Region for writing, if it has been enabled
+    pub const fn pmr_mut(&mut self) -> Option<&mut PersistentMemoryRegion> {
+        self.pmr.as_mut()
     }
 
     /// Identify `NVMe` Controller
@@ -418,10 +1994,54 @@ impl NvmeDevice {
         Ok(())
     }
 
+    /// Reads the limits of the NVM command set, which controllers before `NVMe` 2.0 don't report
+    fn identify_controller_nvm(&mut self) {
+        if self
+            .submit_and_complete_admin(NvmeCommand::identify_controller_nvm)
+            .is_err()
+        {
+            return;
+        }
+        // VSL, WZSL and WUSL are reported as powers of two in units of CAP.MPSMIN, 0 means no limit
+        let mpsmin = (self.get_reg64(NvmeRegs64::CAP as u64) >> 48) & 0xF;
+        let limit = |exponent: u8| match exponent {
+            0 => 0,
+            exponent => PAGESIZE_4KIB << (mpsmin + u64::from(exponent)).min(48),
+        };
+        let limits = &self.buffer[..3];
+        self.verify_limit = limit(limits[0]);
+        self.write_zeroes_limit = limit(limits[1]);
+        self.write_uncorrectable_limit = limit(limits[2]);
+    }
+
     /// Identify `NVMe` Controller
     /// # Errors    
     pub fn identify_controller(&mut self) -> Result<(String, String, String)> {
         self.submit_and_complete_admin(NvmeCommand::identify_controller)?;
+
+        let controller_data: IdentifyControllerData =
+            unsafe { *(self.buffer.virt as *const IdentifyControllerData) };
+
+        // MDTS is reported as a power of two in units of CAP.MPSMIN, 0 means no limit
+        let mpsmin = ((self.get_reg64(NvmeRegs64::CAP as u64) >> 48) & 0xF) as u32;
+        self.max_transfer_size = match controller_data.mdts {
+            0 => MAX_PRP_TRANSFER_SIZE,
+            mdts => {
+                let shift = (u32::from(mdts) + mpsmin).min(PRP_LIST_ENTRIES.trailing_zeros());
+                PAGESIZE_4KIB << shift
+            }
+        };
+        self.sgls = controller_data.sgls;
+        self.oncs = controller_data.oncs;
+        self.fuses = controller_data.fuses;
+        self.volatile_write_cache = controller_data.vwc & VWC_PRESENT != 0;
+        self.ctratt = controller_data.ctratt;
+        self.crdt = [
+            controller_data.crdt1,
+            controller_data.crdt2,
+            controller_data.crdt3,
+        ];
+
         let mut serial = String::new();
         let data = &self.buffer;
 
@@ -455,11 +2075,51 @@ impl NvmeDevice {
         Ok((model, serial, firmware))
     }
 
+    /// Entries an I/O queue may have at most, as reported by the controller in CAP.MQES
+    #[must_use]
+    pub const fn max_queue_len(&self) -> usize {
+        self.max_queue_len
+    }
+
+    /// Maximum bytes a single command transfers (MDTS), larger I/O is split into several commands
+    #[must_use]
+    pub const fn max_transfer_size(&self) -> usize {
+        self.max_transfer_size
+    }
+
+    fn check_queue_len(&self, len: usize) -> Result<()> {
+        if (2..=self.max_queue_len).contains(&len) {
+            Ok(())
+        } else {
+            Err(format!(
+                "queue length {len} not supported, must be between 2 and {}",
+                self.max_queue_len
+            )
+            .into())
+        }
+    }
+
     // 1 to 1 Submission/Completion Queue Mapping
+    /// Creates a completion queue and a submission queue with `len` entries each, memory is allocated for exactly that many
     ///
     /// # Panics
     /// # Errors
+    /// Returns an error if `len` exceeds `max_queue_len`
     pub fn create_io_queue_pair(&mut self, len: usize) -> Result<NvmeQueuePair> {
+        self.create_io_queue_pair_with_priority(len, QueuePriority::default())
+    }
+
+    /// Creates a queue pair as `create_io_queue_pair`, whose submission queue has priority class `priority`.
+    /// The priority only takes effect if the controller uses weighted round robin arbitration.
+    ///
+    /// # Panics
+    /// # Errors
+    pub fn create_io_queue_pair_with_priority(
+        &mut self,
+        len: usize,
+        priority: QueuePriority,
+    ) -> Result<NvmeQueuePair> {
+        self.check_queue_len(len)?;
         let q_id = self.q_id;
         // println!("Requesting i/o queue pair with id {q_id}");
 
@@ -468,51 +2128,226 @@ impl NvmeDevice {
 
         let dbl = self.addr as usize + offset;
 
+        // MSI-X vector 0 belongs to the admin queue, so queue ids can be used as vectors
+        let interrupt = if self.interrupts.is_empty() {
+            None
+        } else {
+            Some(
+                self.interrupts
+                    .get(q_id as usize)
+                    .ok_or_else(|| Error::Vfio(format!("no MSI-X vector left for queue {q_id}")))?
+                    .as_raw_fd(),
+            )
+        };
+
+        let zeroes = self.zero_buffer()?;
         let comp_queue = CompletionQueue::new(&self.allocator, len, dbl)?;
-        let comp = self.submit_and_complete_admin(|c_id, _| {
-            NvmeCommand::create_io_completion_queue(
+        let created = self.submit_and_complete_admin(|c_id, _| {
+            let command = NvmeCommand::create_io_completion_queue(
                 c_id,
                 q_id,
                 comp_queue.get_addr(),
                 (len - 1) as u16,
-            )
-        })?;
+            );
+            if interrupt.is_some() {
+                command.with_interrupt_vector(q_id)
+            } else {
+                command
+            }
+        });
+        if let Err(e) = created {
+            self.deallocate(&comp_queue.commands)?;
+            return Err(e);
+        }
 
-        let dbl = self.addr as usize + 0x1000 + ((4 << self.dstrd) * (2 * q_id) as usize);
-        let sub_queue = SubmissionQueue::new(&self.allocator, len, dbl)?;
-        let comp = self.submit_and_complete_admin(|c_id, _| {
-            NvmeCommand::create_io_submission_queue(
-                c_id,
-                q_id,
-                sub_queue.get_addr(),
-                (len - 1) as u16,
-                q_id,
-            )
-        })?;
+        let sub_queue = match self.create_io_submission_queue(q_id, q_id, len, priority) {
+            Ok(sub_queue) => sub_queue,
+            Err(e) => {
+                // the id of the completion queue is handed out again, so it must not stay behind
+                self.submit_and_complete_admin(|c_id, _| {
+                    NvmeCommand::delete_io_completion_queue(c_id, q_id)
+                })?;
+                self.deallocate(&comp_queue.commands)?;
+                return Err(e);
+            }
+        };
 
         self.q_id += 1;
         Ok(NvmeQueuePair {
             id: q_id,
-            sub_queue,
             comp_queue,
+            sub_queues: vec![sub_queue],
+            max_transfer_size: self.max_transfer_size,
+            sgls: self.sgls,
+            oncs: self.oncs,
+            fuses: self.fuses,
+            write_zeroes_limit: self.write_zeroes_limit,
+            verify_limit: self.verify_limit,
+            write_uncorrectable_limit: self.write_uncorrectable_limit,
+            volatile_write_cache: self.volatile_write_cache,
+            zeroes,
+            crdt: self.crdt,
+            pending_retries: Vec::new(),
+            ready: VecDeque::new(),
+            interrupt,
+            poll_timer: None,
+            retry_policy: self.retry_policy,
+            command_timeout: self.command_timeout,
+            poll_strategy: PollStrategy::default(),
+            latency_estimate: None,
+            stats: NvmeStats::default(),
         })
     }
 
+    fn create_io_submission_queue(
+        &mut self,
+        sq_id: u16,
+        cq_id: u16,
+        len: usize,
+        priority: QueuePriority,
+    ) -> Result<IoSubmissionQueue> {
+        self.check_queue_len(len)?;
+        let dbl = self.addr as usize + 0x1000 + ((4 << self.dstrd) * (2 * sq_id) as usize);
+        let queue = match self.cmb.as_ref().filter(|_| self.cmb_submission_queues) {
+            Some(cmb) => {
+                let commands = cmb
+                    .allocate_region(std::mem::size_of::<NvmeCommand>() * len)
+                    .ok_or("no room left in the controller memory buffer")?;
+                SubmissionQueue::with_commands(commands, len, dbl)
+            }
+            None => SubmissionQueue::new(&self.allocator, len, dbl)?,
+        };
+        // PRP1 plus a list of this size covers the maximum transfer size
+        let list_size = (self.max_transfer_size / PAGESIZE_4KIB * 8)
+            .next_power_of_two()
+            .clamp(MIN_LIST_SIZE, PRP_LIST_SIZE);
+        let prp_lists = match self.allocator.allocate(list_size * len) {
+            Ok(prp_lists) => prp_lists,
+            Err(e) => {
+                self.free_submission_queue(&queue)?;
+                return Err(e);
+            }
+        };
+        let created = self.submit_and_complete_admin(|c_id, _| {
+            NvmeCommand::create_io_submission_queue(
+                c_id,
+                sq_id,
+                queue.get_addr(),
+                (len - 1) as u16,
+                cq_id,
+            )
+            .with_queue_priority(priority as u8)
+        });
+        if let Err(e) = created {
+            self.free_submission_queue(&queue)?;
+            self.deallocate(&prp_lists)?;
+            return Err(e);
+        }
+
+        Ok(IoSubmissionQueue {
+            id: sq_id,
+            queue,
+            c_ids: CommandIds::new(len),
+            prp_lists,
+            list_size,
+        })
+    }
+
+    /// Creates another submission queue with `len` entries and priority class `priority`, whose commands complete on the completion queue of `qpair`.
+    /// Returns the id of the submission queue, to pass to `submit_io_on`.
+    /// # Errors
+    pub fn attach_io_submission_queue(
+        &mut self,
+        qpair: &mut NvmeQueuePair,
+        len: usize,
+        priority: QueuePriority,
+    ) -> Result<u16> {
+        let sq_id = self.q_id;
+        let sub_queue = self.create_io_submission_queue(sq_id, qpair.id, len, priority)?;
+        self.q_id += 1;
+        qpair.sub_queues.push(sub_queue);
+        Ok(sq_id)
+    }
+
+    /// Deletes all submission queues of `qpair`, then its completion queue
     /// # Errors
     pub fn delete_io_queue_pair(&mut self, qpair: &NvmeQueuePair) -> Result<()> {
         // println!("Deleting i/o queue pair with id {}", qpair.id);
-        self.submit_and_complete_admin(|c_id, _| {
-            NvmeCommand::delete_io_submission_queue(c_id, qpair.id)
-        })?;
+        for sub_queue in &qpair.sub_queues {
+            self.submit_and_complete_admin(|c_id, _| {
+                NvmeCommand::delete_io_submission_queue(c_id, sub_queue.id)
+            })?;
+        }
         self.submit_and_complete_admin(|c_id, _| {
             NvmeCommand::delete_io_completion_queue(c_id, qpair.id)
         })?;
 
-        self.deallocate(&qpair.sub_queue.commands)?;
+        for sub_queue in &qpair.sub_queues {
+            self.free_submission_queue(&sub_queue.queue)?;
+            self.deallocate(&sub_queue.prp_lists)?;
+        }
         self.deallocate(&qpair.comp_queue.commands)?;
         Ok(())
     }
 
+    /// Releases the entries of a submission queue, which live in host memory or the controller memory buffer
+    fn free_submission_queue(&self, queue: &SubmissionQueue) -> Result<()> {
+        let in_cmb = self
+            .cmb
+            .as_ref()
+            .is_some_and(|cmb| cmb.release_region(&queue.commands));
+        if in_cmb {
+            Ok(())
+        } else {
+            self.deallocate(&queue.commands)
+        }
+    }
+
+    /// Aborts the command `c_id` of submission queue `sq_id`.
+    /// Returns whether the controller aborted it, the command then completes with status `AbortRequested`.
+    /// # Errors
+    pub fn abort(&mut self, sq_id: u16, c_id: u16) -> Result<bool> {
+        let entry =
+            self.submit_and_complete_admin(|cid, _| NvmeCommand::abort(cid, sq_id, c_id))?;
+        Ok(entry.command_specific & 1 == 0)
+    }
+
+    /// Aborts the commands of `qpair` outstanding for longer than its command timeout.
+    /// Returns the requests they belong to, which complete with status `AbortRequested` once the abort succeeded.
+    /// # Errors
+    pub fn abort_expired(&mut self, qpair: &mut NvmeQueuePair) -> Result<Vec<NvmeRequest>> {
+        let Some(timeout) = qpair.command_timeout else {
+            return Ok(Vec::new());
+        };
+
+        let mut requests = Vec::new();
+        for sub_queue in &mut qpair.sub_queues {
+            for (c_id, request) in sub_queue.c_ids.expired(timeout) {
+                self.abort(sub_queue.id, c_id)?;
+                // give the controller another timeout to complete the aborted command
+                sub_queue.c_ids.restart_timeout(c_id);
+                let request = NvmeRequest {
+                    sq_id: sub_queue.id,
+                    c_id: request,
+                };
+                if !requests.contains(&request) {
+                    requests.push(request);
+                }
+            }
+        }
+        Ok(requests)
+    }
+
+    /// Handle of the active namespace `id`, which I/O calls take to address it
+    /// # Errors
+    /// Returns an error if there is no active namespace `id`
+    pub fn namespace(&self, id: u32) -> Result<NvmeNamespace> {
+        self.namespaces
+            .get(&id)
+            .copied()
+            .ok_or_else(|| format!("namespace {id} is not active").into())
+    }
+
     pub fn identify_namespace_list(&mut self, base: u32) -> Vec<u32> {
         self.submit_and_complete_admin(|c_id, addr| {
             NvmeCommand::identify_namespace_list(c_id, addr, base)
@@ -550,40 +2385,91 @@ impl NvmeDevice {
             0
         };
 
-        // TODO: check metadata?
-        println!("Namespace {id}, Size: {size}, Blocks: {blocks}, Block size: {block_size}");
+        let metadata_size = u64::from(namespace_data.lba_format_support[flba_idx] as u16);
+        let extended_lba = namespace_data.flbas & FLBAS_EXTENDED != 0;
+        let pi_type = match namespace_data.dps & 0b111 {
+            1 => Some(PiType::Type1),
+            2 => Some(PiType::Type2),
+            3 => Some(PiType::Type3),
+            _ => None,
+        };
+        let protection = match (pi_type, self.pi_guard(id, flba_idx)) {
+            (Some(pi_type), Some((guard, storage_tag_size))) => Some(PiFormat {
+                pi_type,
+                guard,
+                first: namespace_data.dps & DPS_PI_FIRST != 0,
+                storage_tag_size,
+            }),
+            _ => None,
+        };
+        println!(
+            "Namespace {id}, Size: {size}, Blocks: {blocks}, Block size: {block_size}, Metadata: {metadata_size}, Protection: {protection:?}"
+        );
 
         let namespace = NvmeNamespace {
             id,
             blocks,
-            block_size,
+            // extended LBAs carry their metadata in the data buffer
+            block_size: if extended_lba && block_size != 0 {
+                block_size + metadata_size
+            } else {
+                block_size
+            },
+            metadata_size,
+            extended_lba,
+            protection,
+            max_copy_range_blocks: namespace_data.mssrl,
+            max_copy_blocks: namespace_data.mcl,
+            max_copy_ranges: u16::from(namespace_data.msrc) + 1,
         };
         self.namespaces.insert(id, namespace);
         namespace
     }
 
-    /// TODO: currently namespace 1 is hardcoded
-    /// # Errors
-    pub fn write(&mut self, data: &impl DmaSlice, mut lba: u64) -> Result<()> {
-        for chunk in data.chunks(2 * 4096) {
-            let blocks = (chunk.slice.len() as u64 + 512 - 1) / 512;
-            self.namespace_io(1, blocks, lba, chunk.phys_addr as u64, true);
-            lba += blocks;
+    /// Guard and storage tag size of the protection information of LBA format `flba_idx` of namespace `id`,
+    /// from its extended LBA format if the controller reports those.
+    /// `None` for the 64 bit guard format and unsupported storage tag sizes.
+    fn pi_guard(&mut self, id: u32, flba_idx: usize) -> Option<(PiGuard, u8)> {
+        if self.ctratt & CTRATT_ELBAS == 0 {
+            return Some((PiGuard::Crc16, 0));
+        }
+        self.submit_and_complete_admin(|c_id, addr| {
+            NvmeCommand::identify_namespace_nvm(c_id, addr, id)
+        })
+        .ok()?;
+        // ELBAF of the NVM command set identify namespace data starts at byte 12
+        let offset = 12 + flba_idx * 4;
+        let elbaf = u32::from_le_bytes(self.buffer[offset..offset + 4].try_into().ok()?);
+        let storage_tag_size = (elbaf & 0x7F) as u8;
+        match (elbaf >> 7) & 0b11 {
+            0 if storage_tag_size <= 32 => Some((PiGuard::Crc16, storage_tag_size)),
+            // the reference tag has to keep at least 32 bits next to the storage tag
+            1 if storage_tag_size <= 48 => Some((PiGuard::Crc32c, storage_tag_size)),
+            _ => None,
         }
+    }
 
-        Ok(())
+    /// `NVMe` write of `DmaSlice` to `ns`
+    /// # Errors
+    /// Returns an error if `data` isn't made up of whole blocks of `ns`, exceeds `ns` or a command failed
+    pub fn write(&mut self, ns: &NvmeNamespace, data: &impl DmaSlice, lba: u64) -> Result<()> {
+        self.prp_io(ns, data, lba, true, false)
     }
 
-    /// TODO: currently namespace 1 is hardcoded
     /// # Errors
+    /// Returns an error if `data` isn't made up of whole blocks of `ns`, exceeds `ns` or a command failed
     pub fn write_prp(
         &mut self,
+        ns: &NvmeNamespace,
         data: &impl DmaSlice,
         mut lba: u64,
         write: bool,
     ) -> Result<Duration> {
+        let chunk_size = chunk_size(ns, (128 * 4096).min(self.max_transfer_size));
+        let blocks = check_buffer(ns, data, chunk_size)?;
+        ns.check_range(lba, blocks)?;
         let mut total = Duration::ZERO;
-        for chunk in data.chunks(128 * 4096) {
+        for chunk in data.chunks(chunk_size) {
             let chunk_len = chunk.slice.len();
             let prp_pages = chunk_len / PAGESIZE_4KIB;
             // println!("received {} prp pages", prp_pages);
@@ -592,9 +2478,9 @@ impl NvmeDevice {
                 self.prp_list[i] = (chunk.phys_addr + i * 4096) as u64;
             }
 
-            let blocks = (chunk.slice.len() as u64 + 512 - 1) / 512;
+            let blocks = chunk.slice.len() as u64 / ns.block_size;
             let start = Instant::now();
-            self.namespace_io(1, blocks, lba, chunk.phys_addr as u64, write);
+            self.namespace_io(ns, blocks, lba, chunk.phys_addr as u64, write)?;
             let elapsed = start.elapsed();
             total += elapsed;
 
@@ -606,39 +2492,109 @@ impl NvmeDevice {
         Ok(total)
     }
 
-    /// `NVMe` read to `DmaSlice`
+    /// `NVMe` read of `ns` to `DmaSlice`
+    /// # Errors
+    /// Returns an error if `dest` isn't made up of whole blocks of `ns`, exceeds `ns` or a command failed
+    pub fn read(&mut self, ns: &NvmeNamespace, dest: &impl DmaSlice, lba: u64) -> Result<()> {
+        self.prp_io(ns, dest, lba, false, false)
+    }
+
+    /// Writes `data` as `write` with Force Unit Access, so it is in non-volatile media once this returns
     /// # Errors
-    pub fn read(&mut self, dest: &impl DmaSlice, mut lba: u64) -> Result<()> {
-        // let ns = *self.namespaces.get(&1).unwrap();
-        for chunk in dest.chunks(2 * 4096) {
-            let blocks = (chunk.slice.len() as u64 + 512 - 1) / 512;
-            self.namespace_io(1, blocks, lba, chunk.phys_addr as u64, false);
+    /// Returns an error if `data` isn't made up of whole blocks of `ns`, exceeds `ns` or a command failed
+    pub fn write_fua(&mut self, ns: &NvmeNamespace, data: &impl DmaSlice, lba: u64) -> Result<()> {
+        self.prp_io(ns, data, lba, true, true)
+    }
+
+    /// Transfers `data` with a command per chunk PRP1 and PRP2 cover, or through the device buffer and its PRP list
+    /// if a block of `ns` doesn't fit into the two pages
+    fn prp_io(
+        &mut self,
+        ns: &NvmeNamespace,
+        data: &impl DmaSlice,
+        mut lba: u64,
+        write: bool,
+        fua: bool,
+    ) -> Result<()> {
+        let offset = data
+            .chunks(PAGESIZE_4KIB)
+            .next()
+            .map_or(0, |chunk| chunk.phys_addr % PAGESIZE_4KIB);
+        let direct = prp_chunk_size(ns, offset) != 0;
+        let chunk_size = if direct {
+            prp_chunk_size(ns, offset)
+        } else {
+            chunk_size(ns, self.buffer.size.min(self.max_transfer_size))
+        };
+        let blocks = check_buffer(ns, data, chunk_size)?;
+        ns.check_range(lba, blocks)?;
+        for chunk in data.chunks(chunk_size) {
+            let len = chunk.slice.len();
+            let blocks = len as u64 / ns.block_size;
+            let addr = if direct {
+                chunk.phys_addr as u64
+            } else {
+                if write {
+                    self.buffer[..len].copy_from_slice(chunk.slice);
+                }
+                self.buffer.phys as u64
+            };
+            let ptr1 = self.namespace_io_prp2(ns, blocks, addr);
+            self.submit_and_complete_io(|c_id| {
+                let command = if write {
+                    NvmeCommand::io_write
+                } else {
+                    NvmeCommand::io_read
+                };
+                let entry = command(c_id, ns.id, lba, (blocks - 1) as u16, addr, ptr1)
+                    .with_protection(ns.inserted_protection(lba), ns.protection);
+                if fua {
+                    entry.with_fua()
+                } else {
+                    entry
+                }
+            })?;
+            if !direct && !write {
+                chunk.slice.copy_from_slice(&self.buffer[..len]);
+            }
             lba += blocks;
         }
         Ok(())
     }
 
+    /// Writes `data` to `ns` through the device buffer, the rest of a partially written last block is zeroed
     /// # Errors
-    /// # Panics
-    pub fn write_copied(&mut self, data: &[u8], mut lba: u64) -> Result<()> {
-        let ns = *self.namespaces.get(&1).unwrap();
-        for chunk in data.chunks(128 * 4096) {
+    /// Returns an error if the block size of `ns` is not supported, `data` exceeds `ns` or a command failed
+    pub fn write_copied(&mut self, ns: &NvmeNamespace, data: &[u8], mut lba: u64) -> Result<()> {
+        let chunk_size = chunk_size(ns, (128 * 4096).min(self.max_transfer_size));
+        if chunk_size == 0 {
+            return Err(format!("block size {} is not supported", ns.block_size).into());
+        }
+        ns.check_range(lba, (data.len() as u64).div_ceil(ns.block_size))?;
+        for chunk in data.chunks(chunk_size) {
+            let blocks = (chunk.len() as u64).div_ceil(ns.block_size);
+            let bytes = (blocks * ns.block_size) as usize;
             self.buffer[..chunk.len()].copy_from_slice(chunk);
-            let blocks = (chunk.len() as u64 + ns.block_size - 1) / ns.block_size;
-            self.namespace_io(1, blocks, lba, self.buffer.phys as u64, true);
+            self.buffer[chunk.len()..bytes].fill(0);
+            self.namespace_io(ns, blocks, lba, self.buffer.phys as u64, true)?;
             lba += blocks;
         }
 
         Ok(())
     }
 
+    /// Reads `dest.len()` bytes of `ns` through the device buffer
     /// # Errors
-    /// # Panics
-    pub fn read_copied(&mut self, dest: &mut [u8], mut lba: u64) -> Result<()> {
-        let ns = *self.namespaces.get(&1).unwrap();
-        for chunk in dest.chunks_mut(128 * 4096) {
-            let blocks = (chunk.len() as u64 + ns.block_size - 1) / ns.block_size;
-            self.namespace_io(1, blocks, lba, self.buffer.phys as u64, false);
+    /// Returns an error if the block size of `ns` is not supported, `dest` exceeds `ns` or a command failed
+    pub fn read_copied(&mut self, ns: &NvmeNamespace, dest: &mut [u8], mut lba: u64) -> Result<()> {
+        let chunk_size = chunk_size(ns, (128 * 4096).min(self.max_transfer_size));
+        if chunk_size == 0 {
+            return Err(format!("block size {} is not supported", ns.block_size).into());
+        }
+        ns.check_range(lba, (dest.len() as u64).div_ceil(ns.block_size))?;
+        for chunk in dest.chunks_mut(chunk_size) {
+            let blocks = (chunk.len() as u64).div_ceil(ns.block_size);
+            self.namespace_io(ns, blocks, lba, self.buffer.phys as u64, false)?;
             lba += blocks;
             chunk.copy_from_slice(&self.buffer[..chunk.len()]);
         }
@@ -654,18 +2610,21 @@ impl NvmeDevice {
         write: bool,
     ) -> Option<usize> {
         assert!(blocks > 0);
-        assert!(blocks <= 0x1_0000);
+        assert!(blocks <= MAX_COMMAND_BLOCKS);
         let q_id = 1;
 
         let bytes = blocks * ns.block_size;
-        let ptr1 = if bytes <= 4096 {
-            0
-        } else if bytes <= 8192 {
-            addr + 4096 // self.page_size
-        } else {
-            // idk if this works
-            let offset = (addr - self.buffer.phys as u64) / 8;
-            self.prp_list.phys as u64 + offset
+        // only PRP1 may have an offset into its page, as the blocks of extended LBAs do
+        let page_size = PAGESIZE_4KIB as u64;
+        let offset = addr % page_size;
+        let ptr1 = match (offset + bytes).div_ceil(page_size) {
+            1 => 0,
+            2 => addr - offset + page_size,
+            _ => {
+                // the list entry of the page following the one of `addr` in the device buffer
+                let page = (addr - self.buffer.phys as u64) / page_size;
+                self.prp_list.phys as u64 + (page + 1) * 8
+            }
         };
 
         let entry = if write {
@@ -673,7 +2632,7 @@ impl NvmeDevice {
                 self.io_sq.tail as u16,
                 ns.id,
                 lba,
-                blocks as u16 - 1,
+                (blocks - 1) as u16,
                 addr,
                 ptr1,
             )
@@ -682,159 +2641,158 @@ impl NvmeDevice {
                 self.io_sq.tail as u16,
                 ns.id,
                 lba,
-                blocks as u16 - 1,
+                (blocks - 1) as u16,
                 addr,
                 ptr1,
             )
-        };
+        }
+        .with_protection(ns.inserted_protection(lba), ns.protection);
         self.io_sq.submit_checked(entry)
     }
 
-    fn complete_io(&mut self, step: u64) -> Option<u16> {
+    fn complete_io(&mut self, step: u64) -> Result<u16> {
         let q_id = 1;
 
-        let (tail, c_entry, _) = self.io_cq.complete_n(step as usize);
-        self.write_reg_idx(NvmeArrayRegs::CQyHDBL, q_id as u16, tail as u32);
+        let deadline = self.command_timeout.map(|timeout| Instant::now() + timeout);
+        let Some((tail, c_entry, _)) = self.io_cq.complete_n(step as usize, deadline) else {
+            // command ids of this queue are the slots they were submitted to, and the head only moves
+            // once all commands submitted before completed, so every command after it expired
+            let c_ids: Vec<u16> = self
+                .io_sq
+                .submitted_slots()
+                .map(|slot| slot as u16)
+                .collect();
+            for &c_id in &c_ids {
+                self.abort(q_id, c_id)?;
+            }
+            return Err(Error::Timeout(format!(
+                "i/o commands {c_ids:?} did not complete within {:?}",
+                self.command_timeout.unwrap_or_default()
+            )));
+        };
+        self.write_reg_idx(NvmeArrayRegs::CQyHDBL, q_id, tail as u32);
 
-        let status = c_entry.status >> 1;
-        if status != 0 {
-            eprintln!(
-                "Status: 0x{:x}, Status Code 0x{:x}, Status Code Type: 0x{:x}",
-                status,
-                status & 0xFF,
-                (status >> 8) & 0x7
-            );
-            eprintln!("{c_entry:?}");
-            return None;
+        let status = NvmeStatus::new(c_entry.status >> 1);
+        if !status.is_success() {
+            return Err(Error::Nvme(status));
         }
         self.stats.completions += 1;
-        Some(c_entry.sq_head)
+        Ok(c_entry.sq_head)
     }
 
     /// # Errors
-    /// # Panics
+    /// Returns an error if `data` isn't made up of whole blocks of `ns`, exceeds `ns` or a command failed
     pub fn batched_write(
         &mut self,
-        ns_id: u32,
+        ns: &NvmeNamespace,
         data: &[u8],
         mut lba: u64,
         batch_len: u64,
     ) -> Result<()> {
-        let ns = *self.namespaces.get(&ns_id).unwrap();
-        let block_size = 512;
-        let q_id = 1;
+        ns.check_range(lba, check_batch(ns, data.len())?)?;
 
         for chunk in data.chunks(PAGESIZE_2MIB) {
             self.buffer[..chunk.len()].copy_from_slice(chunk);
-            let tail = self.io_sq.tail;
-
-            let batch_len = std::cmp::min(batch_len, chunk.len() as u64 / block_size);
-            let batch_size = chunk.len() as u64 / batch_len;
-            let blocks = batch_size / ns.block_size;
-
-            for i in 0..batch_len {
-                if let Some(tail) = self.submit_io(
-                    &ns,
-                    self.buffer.phys as u64 + i * batch_size,
-                    blocks,
-                    lba,
-                    true,
-                ) {
-                    self.stats.submissions += 1;
-                    self.write_reg_idx(NvmeArrayRegs::SQyTDBL, q_id as u16, tail as u32);
-                } else {
-                    eprintln!("tail: {tail}, batch_len: {batch_len}, batch_size: {batch_size}, blocks: {blocks}");
-                }
-                lba += blocks;
-            }
-            self.io_sq.head = self.complete_io(batch_len).unwrap() as usize;
+            self.batched_io(ns, chunk.len(), lba, batch_len, true)?;
+            lba += chunk.len() as u64 / ns.block_size;
         }
 
         Ok(())
     }
 
     /// # Errors
-    /// # Panics
+    /// Returns an error if `data` isn't made up of whole blocks of `ns`, exceeds `ns` or a command failed
     pub fn batched_read(
         &mut self,
-        ns_id: u32,
+        ns: &NvmeNamespace,
         data: &mut [u8],
         mut lba: u64,
         batch_len: u64,
     ) -> Result<()> {
-        let ns = *self.namespaces.get(&ns_id).unwrap();
-        let block_size = 512;
-        let q_id = 1;
+        ns.check_range(lba, check_batch(ns, data.len())?)?;
 
         for chunk in data.chunks_mut(PAGESIZE_2MIB) {
-            let tail = self.io_sq.tail;
-
-            let batch_len = std::cmp::min(batch_len, chunk.len() as u64 / block_size);
-            let batch_size = chunk.len() as u64 / batch_len;
-            let blocks = batch_size / ns.block_size;
-
-            for i in 0..batch_len {
-                if let Some(tail) = self.submit_io(
-                    &ns,
-                    self.buffer.phys as u64 + i * batch_size,
-                    blocks,
-                    lba,
-                    false,
-                ) {
-                    self.stats.submissions += 1;
-                    self.write_reg_idx(NvmeArrayRegs::SQyTDBL, q_id as u16, tail as u32);
-                } else {
-                    eprintln!("tail: {tail}, batch_len: {batch_len}, batch_size: {batch_size}, blocks: {blocks}");
-                }
-                lba += blocks;
-            }
-            self.io_sq.head = self.complete_io(batch_len).unwrap() as usize;
+            self.batched_io(ns, chunk.len(), lba, batch_len, false)?;
             chunk.copy_from_slice(&self.buffer[..chunk.len()]);
+            lba += chunk.len() as u64 / ns.block_size;
         }
         Ok(())
     }
 
-    fn namespace_io(&mut self, ns_id: u32, blocks: u64, lba: u64, addr: u64, write: bool) {
-        assert!(blocks > 0);
-        assert!(blocks <= 0x1_0000);
-
+    /// Transfers the first `len` bytes of the device buffer with `batch_len` commands submitted at once,
+    /// or more if a command would exceed the maximum transfer size
+    fn batched_io(
+        &mut self,
+        ns: &NvmeNamespace,
+        len: usize,
+        lba: u64,
+        batch_len: u64,
+        write: bool,
+    ) -> Result<()> {
         let q_id = 1;
+        let total = len as u64 / ns.block_size;
+        let max_blocks = chunk_size(ns, self.max_transfer_size) as u64 / ns.block_size;
+        let batch_len = batch_len.max(total.div_ceil(max_blocks)).min(total);
+        let blocks = total.div_ceil(batch_len);
+
+        let mut submitted = 0;
+        let mut offset = 0;
+        while offset < total {
+            let tail = self.io_sq.tail;
+            let blocks = blocks.min(total - offset);
+            let addr = self.buffer.phys as u64 + offset * ns.block_size;
+            let Some(tail) = self.submit_io(ns, addr, blocks, lba + offset, write) else {
+                if submitted > 0 {
+                    self.io_sq.head = self.complete_io(submitted)? as usize;
+                }
+                return Err(format!(
+                    "submission queue full, tail: {tail}, batch_len: {batch_len}, blocks: {blocks}"
+                )
+                .into());
+            };
+            self.stats.submissions += 1;
+            self.write_reg_idx(NvmeArrayRegs::SQyTDBL, q_id, tail as u32);
+            submitted += 1;
+            offset += blocks;
+        }
+        self.io_sq.head = self.complete_io(submitted)? as usize;
+        Ok(())
+    }
 
-        let bytes = blocks * 512;
-        let ptr1 = if bytes <= 4096 {
-            0
-        } else if bytes <= 8192 {
-            // self.buffer.phys as u64 + 4096 // self.page_size
-            addr + 4096 // self.page_size
-        } else {
-            self.prp_list.phys as u64 + 8
-        };
-
-        let entry = if write {
-            NvmeCommand::io_write(
-                self.io_sq.tail as u16,
-                ns_id,
-                lba,
-                blocks as u16 - 1,
-                addr,
-                ptr1,
-            )
+    fn namespace_io(
+        &mut self,
+        ns: &NvmeNamespace,
+        blocks: u64,
+        lba: u64,
+        addr: u64,
+        write: bool,
+    ) -> Result<()> {
+        let ptr1 = self.namespace_io_prp2(ns, blocks, addr);
+        let command = if write {
+            NvmeCommand::io_write
         } else {
-            NvmeCommand::io_read(
-                self.io_sq.tail as u16,
-                ns_id,
-                lba,
-                blocks as u16 - 1,
-                addr,
-                ptr1,
-            )
+            NvmeCommand::io_read
         };
+        self.submit_and_complete_io(|c_id| {
+            command(c_id, ns.id, lba, (blocks - 1) as u16, addr, ptr1)
+                .with_protection(ns.inserted_protection(lba), ns.protection)
+        })
+    }
 
-        let tail = self.io_sq.submit(entry);
-        self.stats.submissions += 1;
+    /// PRP2 of a transfer of `blocks` blocks of `ns` at `addr`, which has to be part of the device buffer if it spans more than two pages
+    fn namespace_io_prp2(&self, ns: &NvmeNamespace, blocks: u64, addr: u64) -> u64 {
+        assert!(blocks > 0);
+        assert!(blocks <= MAX_COMMAND_BLOCKS);
 
-        self.write_reg_idx(NvmeArrayRegs::SQyTDBL, q_id as u16, tail as u32);
-        self.io_sq.head = self.complete_io(1).unwrap() as usize;
+        let bytes = blocks * ns.block_size;
+        let page_size = PAGESIZE_4KIB as u64;
+        let offset = addr % page_size;
+        match (offset + bytes).div_ceil(page_size) {
+            1 => 0,
+            2 => addr - offset + page_size,
+            // only the device buffer spans more pages, its PRP list is prepared in `prp_list`
+            _ => self.prp_list.phys as u64 + 8,
+        }
     }
 
     fn submit_and_complete_admin<F: FnOnce(u16, usize) -> NvmeCommand>(
@@ -842,21 +2800,165 @@ impl NvmeDevice {
         cmd_init: F,
     ) -> Result<NvmeCompletion> {
         let cid = self.admin_sq.tail;
-        let tail = self.admin_sq.submit(cmd_init(cid as u16, self.buffer.phys));
+        let mut command = cmd_init(cid as u16, self.buffer.phys);
+        let mut attempts = 0;
+        loop {
+            let tail = self.admin_sq.submit(command);
+            self.write_reg_idx(NvmeArrayRegs::SQyTDBL, 0, tail as u32);
+            attempts += 1;
+
+            let entry = self.complete_admin(command.c_id)?;
+            let status = NvmeStatus::new(entry.status >> 1);
+            if status.is_success() {
+                return Ok(entry);
+            }
+
+            match self.retry_policy.retry_delay(status, attempts, self.crdt) {
+                Some(delay) => {
+                    self.stats.retries += 1;
+                    std::thread::sleep(delay);
+                    // command ids only need to be unique among outstanding commands
+                    command.c_id = self.admin_sq.tail as u16;
+                }
+                None => return Err(Error::Nvme(status)),
+            }
+        }
+    }
+
+    /// Spins until the admin command `c_id` completed.
+    /// Aborts the command and returns `Error::Timeout` if it doesn't complete within the command timeout.
+    fn complete_admin(&mut self, c_id: u16) -> Result<NvmeCompletion> {
+        if let Some(entry) = self.wait_admin(c_id) {
+            return Ok(entry);
+        }
+
+        let abort_id = self.admin_sq.tail as u16;
+        let tail = self.admin_sq.submit(NvmeCommand::abort(abort_id, 0, c_id));
         self.write_reg_idx(NvmeArrayRegs::SQyTDBL, 0, tail as u32);
-        let (head, entry, _) = self.admin_cq.complete_spin();
-        self.write_reg_idx(NvmeArrayRegs::CQyHDBL, 0, head as u32);
-        let status = entry.status >> 1;
-        if status != 0 {
-            eprintln!(
-                "Status: 0x{:x}, Status Code 0x{:x}, Status Code Type: 0x{:x}",
-                status,
-                status & 0xFF,
-                (status >> 8) & 0x7
+        self.wait_admin(abort_id);
+
+        Err(Error::Timeout(format!(
+            "admin command {c_id} did not complete within {:?}",
+            self.command_timeout.unwrap_or_default()
+        )))
+    }
+
+    /// Spins until the admin command `c_id` completed, skipping late completions of timed out commands.
+    /// Returns `None` if the command timeout passes first.
+    fn wait_admin(&mut self, c_id: u16) -> Option<NvmeCompletion> {
+        let deadline = self.command_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let (head, entry, _) = self.admin_cq.complete_spin(deadline)?;
+            self.write_reg_idx(NvmeArrayRegs::CQyHDBL, 0, head as u32);
+            self.admin_sq.head = entry.sq_head as usize;
+            if entry.c_id == c_id {
+                return Some(entry);
+            }
+        }
+    }
+
+    /// Runs a Dataset Management command for `ranges` of `ns`, e.g. to deallocate them with `DsmAttributes::DEALLOCATE`
+    /// # Errors
+    /// Returns an error if the controller doesn't support Dataset Management, the ranges are invalid or exceed `ns`, or the command failed
+    pub fn dataset_management(
+        &mut self,
+        ns: &NvmeNamespace,
+        ranges: &[DsmRange],
+        attributes: DsmAttributes,
+    ) -> Result<()> {
+        check_dataset_management(self.oncs, ns, ranges, DsmRange::MAX_RANGES)?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                ranges.as_ptr().cast::<u8>(),
+                self.buffer.virt,
+                std::mem::size_of_val(ranges),
             );
-            return Err("Requesting i/o completion queue failed".into());
         }
-        Ok(entry)
+        let addr = self.buffer.phys as u64;
+        self.submit_and_complete_io(|c_id| {
+            NvmeCommand::dataset_management(c_id, ns.id, addr, ranges.len() as u16, attributes)
+        })
+    }
+
+    /// Whether the controller has a volatile write cache, which writes need a flush or FUA to be durable with
+    #[must_use]
+    pub const fn volatile_write_cache(&self) -> bool {
+        self.volatile_write_cache
+    }
+
+    /// Flushes `ns`, committing the data in the volatile write cache to non-volatile media.
+    /// Does nothing if the controller has no volatile write cache.
+    /// # Errors
+    /// Returns an error if the command failed
+    pub fn flush(&mut self, ns: &NvmeNamespace) -> Result<()> {
+        if !self.volatile_write_cache {
+            return Ok(());
+        }
+        self.submit_and_complete_io(|c_id| NvmeCommand::flush(c_id, ns.id))
+    }
+
+    /// Zeroes `blocks` blocks of `ns` starting at `lba`, split into commands of at most the Write Zeroes size limit.
+    /// With `deallocate` the controller may deallocate the blocks instead, they read as zeroes either way.
+    /// Controllers without Write Zeroes get the blocks written from a zeroed buffer instead.
+    /// # Errors
+    /// Returns an error if the blocks exceed `ns` or a command failed
+    pub fn write_zeroes(
+        &mut self,
+        ns: &NvmeNamespace,
+        mut lba: u64,
+        blocks: u64,
+        deallocate: bool,
+    ) -> Result<()> {
+        ns.check_range(lba, blocks)?;
+        let end = lba + blocks;
+        if self.oncs & ONCS_WRITE_ZEROES != 0 {
+            let max_blocks =
+                limit_blocks(self.write_zeroes_limit, ns.block_size).clamp(1, MAX_COMMAND_BLOCKS);
+            while lba < end {
+                let blocks = (end - lba).min(max_blocks);
+                self.submit_and_complete_io(|c_id| {
+                    NvmeCommand::write_zeroes(c_id, ns.id, lba, (blocks - 1) as u16, deallocate)
+                        .with_protection(ns.inserted_protection(lba), ns.protection)
+                })?;
+                lba += blocks;
+            }
+            return Ok(());
+        }
+
+        self.buffer[..].fill(0);
+        let max_blocks =
+            chunk_size(ns, self.buffer.size.min(self.max_transfer_size)) as u64 / ns.block_size;
+        while lba < end {
+            let blocks = (end - lba).min(max_blocks);
+            self.namespace_io(ns, blocks, lba, self.buffer.phys as u64, true)?;
+            lba += blocks;
+        }
+        Ok(())
+    }
+
+    /// Zeroed buffer for queue pairs to emulate Write Zeroes with, `None` if the controller supports Write Zeroes
+    fn zero_buffer(&mut self) -> Result<Option<Arc<Dma<u8>>>> {
+        if self.oncs & ONCS_WRITE_ZEROES != 0 {
+            return Ok(None);
+        }
+        if self.zeroes.is_none() {
+            let size = self.max_transfer_size.min(PAGESIZE_2MIB);
+            let mut zeroes: Dma<u8> = self.allocator.allocate(size)?;
+            zeroes[..].fill(0);
+            self.zeroes = Some(Arc::new(zeroes));
+        }
+        Ok(self.zeroes.clone())
+    }
+
+    /// Submits the command built by `cmd_init` to the I/O queue of the device and waits for its completion
+    fn submit_and_complete_io<F: FnOnce(u16) -> NvmeCommand>(&mut self, cmd_init: F) -> Result<()> {
+        let q_id = 1;
+        let tail = self.io_sq.submit(cmd_init(self.io_sq.tail as u16));
+        self.stats.submissions += 1;
+
+        self.write_reg_idx(NvmeArrayRegs::SQyTDBL, q_id, tail as u32);
+        self.io_sq.head = self.complete_io(1)? as usize;
+        Ok(())
     }
 
     /// # Panics
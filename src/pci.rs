@@ -26,6 +26,20 @@ pub fn pci_open_resource_ro(pci_addr: &str, resource: &str) -> Result<File> {
     Ok(OpenOptions::new().read(true).write(false).open(path)?)
 }
 
+/// Returns the bus address of BAR `bar`, as listed in the sysfs resource file of the device.
+pub fn read_bar_address(pci_addr: &str, bar: u32) -> Result<u64> {
+    let mut resources = String::new();
+    pci_open_resource_ro(pci_addr, "resource")?.read_to_string(&mut resources)?;
+
+    // one line per resource: start, end and flags in hex
+    resources
+        .lines()
+        .nth(bar as usize)
+        .and_then(|line| line.split_whitespace().next())
+        .and_then(|start| u64::from_str_radix(start.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| Error::Vroom(format!("no address of BAR {bar} for device {pci_addr}")))
+}
+
 /// Reads and returns an u8 at `offset` in `file`.
 pub fn read_io8(file: &mut File, offset: u64) -> Result<u8> {
     file.seek(SeekFrom::Start(offset))?;
@@ -123,6 +123,26 @@ impl Physical {
     }
 }
 
+impl Physical {
+    /// Maps BAR `index` of the device through its sysfs resource file
+    /// # Errors
+    pub fn map_resource_index(&self, index: u32) -> Result<(*mut u8, usize)> {
+        let path = format!("/sys/bus/pci/devices/{}/resource{index}", self.pci_addr);
+
+        let file = fs::OpenOptions::new().read(true).write(true).open(&path)?;
+        let len = fs::metadata(&path)?.len() as usize;
+
+        if len == 0 {
+            return Err(Error::Vroom(format!("Resource{index} len is 0")));
+        }
+
+        // mmap with null ptr to address => kernel chooses address to create mapping
+        let ptr = mmap_fd_unsafe!(len, file.as_raw_fd())?;
+
+        Ok((ptr.cast::<u8>(), len))
+    }
+}
+
 impl Mapping for Physical {
     fn allocate<T>(&self, size: usize) -> Result<Dma<T>> {
         let size = self.page_size.shift_up(size);
@@ -160,19 +180,7 @@ impl Mapping for Physical {
 
     /// Mmaps a pci resource0 and returns a pointer to the mapped memory.
     fn map_resource(&self) -> Result<(*mut u8, usize)> {
-        let path = format!("/sys/bus/pci/devices/{}/resource0", self.pci_addr);
-
-        let file = fs::OpenOptions::new().read(true).write(true).open(&path)?;
-        let len = fs::metadata(&path)?.len() as usize;
-
-        if len == 0 {
-            return Err(Error::Vroom("Resource0 len is 0".to_string()));
-        }
-
-        // mmap with null ptr to address => kernel chooses address to create mapping
-        let ptr = mmap_fd_unsafe!(len, file.as_raw_fd())?;
-
-        Ok((ptr.cast::<u8>(), len))
+        self.map_resource_index(0)
     }
 
     fn deallocate<T>(&self, dma: &Dma<T>) -> Result<()> {
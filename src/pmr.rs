@@ -0,0 +1,147 @@
+use crate::{Error, Result};
+use std::sync::atomic::{fence, Ordering};
+use std::time::Duration;
+
+// PMRCAP.PMRWBM write barrier mechanisms
+const PMRWBM_READ: u32 = 1 << 0;
+const PMRWBM_PMRSTS: u32 = 1 << 1;
+
+/// Fields of the PMRCAP register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PmrCapabilities {
+    // BAR the region is in (BIR)
+    pub bar: u32,
+    // write barrier mechanisms (PMRWBM)
+    pub barriers: u32,
+    // time the region may take to become ready once enabled (PMRTO in PMRTU units)
+    pub ready_timeout: Duration,
+}
+
+impl PmrCapabilities {
+    pub const fn new(pmrcap: u32) -> Self {
+        let pmrto = ((pmrcap >> 16) & 0xFF) as u64;
+        // PMRTU, PMRTO is in 500 ms units or in minutes
+        let ready_timeout = match (pmrcap >> 8) & 0b11 {
+            1 => Duration::from_secs(60 * pmrto),
+            _ => Duration::from_millis(500 * pmrto),
+        };
+        Self {
+            bar: (pmrcap >> 5) & 0b111,
+            barriers: (pmrcap >> 10) & 0xF,
+            ready_timeout,
+        }
+    }
+}
+
+/// Persistent Memory Region, byte addressable memory of the controller that keeps its contents across power loss.
+///
+/// Writes are only guaranteed to be persistent after a following `persist`.
+pub struct PersistentMemoryRegion {
+    virt: *mut u8,
+    size: usize,
+    // PMRSTS register of the controller, reading it may serve as write barrier
+    pmrsts: *const u32,
+    // PMRCAP.PMRWBM
+    barriers: u32,
+}
+
+unsafe impl Send for PersistentMemoryRegion {}
+
+unsafe impl Sync for PersistentMemoryRegion {}
+
+impl PersistentMemoryRegion {
+    pub(crate) const fn new(virt: *mut u8, size: usize, pmrsts: *const u32, barriers: u32) -> Self {
+        Self {
+            virt,
+            size,
+            pmrsts,
+            barriers,
+        }
+    }
+
+    /// Size of the region in bytes
+    #[must_use]
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Start of the mapping of the region, for direct access
+    #[must_use]
+    pub const fn as_ptr(&self) -> *mut u8 {
+        self.virt
+    }
+
+    fn check_bounds(&self, offset: usize, len: usize) -> Result<()> {
+        if offset.checked_add(len).is_some_and(|end| end <= self.size) {
+            Ok(())
+        } else {
+            Err(Error::Vroom(format!(
+                "access of {len} bytes at offset {offset} exceeds the persistent memory region of {} bytes",
+                self.size
+            )))
+        }
+    }
+
+    /// Copies `data` into the region at `offset`, call `persist` to make it persistent
+    /// # Errors
+    /// Returns an error if the write exceeds the region
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.check_bounds(offset, data.len())?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.virt.add(offset), data.len());
+        }
+        Ok(())
+    }
+
+    /// Copies `buf.len()` bytes at `offset` of the region into `buf`
+    /// # Errors
+    /// Returns an error if the read exceeds the region
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        self.check_bounds(offset, buf.len())?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.virt.add(offset), buf.as_mut_ptr(), buf.len());
+        }
+        Ok(())
+    }
+
+    /// Makes all previous writes to the region persistent, using a write barrier mechanism the controller supports
+    /// # Errors
+    /// Returns an error if the controller reports no write barrier mechanism
+    pub fn persist(&self) -> Result<()> {
+        // writes must have left the CPU before the barrier read is issued
+        fence(Ordering::SeqCst);
+        if self.barriers & PMRWBM_READ != 0 {
+            // the completion of a read from the region ensures prior writes are persistent
+            unsafe { std::ptr::read_volatile(self.virt) };
+        } else if self.barriers & PMRWBM_PMRSTS != 0 {
+            unsafe { std::ptr::read_volatile(self.pmrsts) };
+        } else {
+            return Err(Error::Vroom(
+                "controller reports no write barrier mechanism for its persistent memory region"
+                    .to_string(),
+            ));
+        }
+        fence(Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities() {
+        // BIR 2, PMRWBM read barrier, PMRTO 4 in 500 ms units
+        let caps = PmrCapabilities::new(0x0004_0440);
+        assert_eq!(caps.bar, 2);
+        assert_eq!(caps.barriers, PMRWBM_READ);
+        assert_eq!(caps.ready_timeout, Duration::from_secs(2));
+
+        // PMRTU 1, PMRTO 3 in minutes, PMRWBM PMRSTS read
+        let caps = PmrCapabilities::new(0x0003_0900);
+        assert_eq!(caps.bar, 0);
+        assert_eq!(caps.barriers, PMRWBM_PMRSTS);
+        assert_eq!(caps.ready_timeout, Duration::from_mins(3));
+    }
+}
@@ -0,0 +1,474 @@
+use crate::error::{Error, Result};
+use crate::nvme::NvmeNamespace;
+
+/// End-to-end data protection type a namespace is formatted with (DPS.PIT)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiType {
+    /// The reference tag is the lower bits of the LBA of the block
+    Type1,
+    /// The reference tag starts at the initial reference tag of a command and increments by block
+    Type2,
+    /// The reference tag is not checked by the controller
+    Type3,
+}
+
+/// Guard of the protection information, the CRC protecting the data of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiGuard {
+    /// 16 bit guard protection information format, 8 bytes with a T10-DIF CRC16 guard
+    Crc16,
+    /// 32 bit guard protection information format, 16 bytes with a CRC32C guard
+    Crc32c,
+}
+
+/// Protection information format of a namespace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PiFormat {
+    pub pi_type: PiType,
+    pub guard: PiGuard,
+    /// The protection information is in the first bytes of the metadata of a block instead of the last (DPS.PIP)
+    pub first: bool,
+    /// Bits of the storage tag, which takes the upper bits of the space the reference tag shares with it (STS)
+    pub storage_tag_size: u8,
+}
+
+impl PiFormat {
+    /// Bytes of protection information per block
+    #[must_use]
+    pub const fn size(self) -> u64 {
+        match self.guard {
+            PiGuard::Crc16 => 8,
+            PiGuard::Crc32c => 16,
+        }
+    }
+
+    /// Bits of the reference tag, the rest of the storage and reference space, 32 bits for a 16 bit guard and 80 bits for a 32 bit guard
+    #[must_use]
+    pub const fn reference_tag_size(self) -> u32 {
+        let space = match self.guard {
+            PiGuard::Crc16 => 32,
+            PiGuard::Crc32c => 80,
+        };
+        space - self.storage_tag_size as u32
+    }
+}
+
+/// Field of the protection information of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiField {
+    Guard,
+    ApplicationTag,
+    ReferenceTag,
+}
+
+impl std::fmt::Display for PiField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Guard => write!(f, "guard"),
+            Self::ApplicationTag => write!(f, "application tag"),
+            Self::ReferenceTag => write!(f, "reference tag"),
+        }
+    }
+}
+
+/// Protection information handling of a read, write or compare (PRINFO) and the tags its blocks are expected to have
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Protection {
+    /// The controller inserts the protection information on writes and strips it on reads (PRACT),
+    /// no metadata is transferred if it only consists of protection information
+    pub action: bool,
+    /// The controller checks the guard (PRCHK)
+    pub check_guard: bool,
+    /// The controller checks the application tag under `application_mask` (PRCHK)
+    pub check_application_tag: bool,
+    /// The controller checks the reference tag (PRCHK)
+    pub check_reference_tag: bool,
+    /// Reference tag of the first block, the lower bits of its LBA for type 1, truncated to the reference tag size of the format
+    pub reference_tag: u64,
+    /// Storage tag of the blocks, truncated to the storage tag size of the format
+    pub storage_tag: u64,
+    pub application_tag: u16,
+    /// Bits of the application tag which are checked
+    pub application_mask: u16,
+}
+
+impl Protection {
+    /// Checks all fields of the protection information, which the host provides and receives as metadata
+    #[must_use]
+    pub const fn check_all(reference_tag: u64, application_tag: u16) -> Self {
+        Self {
+            action: false,
+            check_guard: true,
+            check_application_tag: true,
+            check_reference_tag: true,
+            reference_tag,
+            storage_tag: 0,
+            application_tag,
+            application_mask: 0xFFFF,
+        }
+    }
+
+    /// PRINFO field of command dword 12
+    pub(crate) const fn prinfo(&self) -> u32 {
+        ((self.action as u32) << 3)
+            | ((self.check_guard as u32) << 2)
+            | ((self.check_application_tag as u32) << 1)
+            | (self.check_reference_tag as u32)
+    }
+
+    /// The protection of the blocks following the first `blocks` blocks of a transfer, split into several commands
+    pub(crate) const fn advance(mut self, pi_type: PiType, blocks: u64) -> Self {
+        if !matches!(pi_type, PiType::Type3) {
+            self.reference_tag = self.reference_tag.wrapping_add(blocks);
+        }
+        self
+    }
+
+    /// The storage and reference space of the protection information of the first block, the storage tag above the reference tag.
+    /// Without protection information only the lower 32 bits of the reference tag are used.
+    pub(crate) const fn tag_space(&self, format: Option<PiFormat>) -> u128 {
+        let (reference_size, storage_size) = match format {
+            Some(format) => (format.reference_tag_size(), format.storage_tag_size as u32),
+            None => (32, 0),
+        };
+        (truncate(self.storage_tag, storage_size) << reference_size)
+            | truncate(self.reference_tag, reference_size)
+    }
+}
+
+/// The lower `bits` bits of `value`
+const fn truncate(value: u64, bits: u32) -> u128 {
+    if bits >= 64 {
+        value as u128
+    } else {
+        (value & ((1 << bits) - 1)) as u128
+    }
+}
+
+impl NvmeNamespace {
+    /// Bytes of data of each block, without its metadata
+    #[must_use]
+    pub const fn data_size(&self) -> u64 {
+        if self.extended_lba {
+            self.block_size - self.metadata_size
+        } else {
+            self.block_size
+        }
+    }
+
+    /// Protection of transfers without metadata buffer, in which the controller inserts and checks the protection information,
+    /// if that is all the metadata of the namespace
+    pub(crate) const fn inserted_protection(&self, lba: u64) -> Protection {
+        match self.protection {
+            Some(format) if !self.extended_lba && self.metadata_size == format.size() => {
+                Protection {
+                    action: true,
+                    check_guard: true,
+                    check_application_tag: false,
+                    check_reference_tag: !matches!(format.pi_type, PiType::Type3),
+                    reference_tag: lba,
+                    storage_tag: 0,
+                    application_tag: 0,
+                    application_mask: 0,
+                }
+            }
+            _ => Protection {
+                action: false,
+                check_guard: false,
+                check_application_tag: false,
+                check_reference_tag: false,
+                reference_tag: 0,
+                storage_tag: 0,
+                application_tag: 0,
+                application_mask: 0,
+            },
+        }
+    }
+
+    /// Checks that transfers to and from the namespace may go without metadata buffer
+    /// # Errors
+    /// Returns `Error::MetadataRequired` if the namespace has separate metadata the controller can't insert
+    pub const fn check_metadata(&self) -> Result<()> {
+        let inserted = match self.protection {
+            Some(format) => self.metadata_size == format.size(),
+            None => false,
+        };
+        if self.metadata_size == 0 || self.extended_lba || inserted {
+            Ok(())
+        } else {
+            Err(Error::MetadataRequired { ns_id: self.id })
+        }
+    }
+
+    /// Generates the protection information of the blocks of `data` as `protection` expects it,
+    /// into `metadata` or, with extended LBAs, the metadata interleaved in `data`
+    /// # Errors
+    /// Returns an error if the namespace has no protection information, or the buffers don't match its blocks
+    pub fn generate_pi(
+        &self,
+        data: &mut [u8],
+        mut metadata: Option<&mut [u8]>,
+        protection: &Protection,
+    ) -> Result<()> {
+        let (format, blocks) = self.pi_blocks(data.len(), metadata.as_deref().map(<[u8]>::len))?;
+        for block in 0..blocks {
+            let (data, metadata) = self.block(data, metadata.as_deref_mut(), block);
+            let (guarded, pi) = metadata.split_at_mut(pi_offset(format, metadata.len()));
+            let guard = guard(format, data, guarded);
+            let protection = protection.advance(format.pi_type, block);
+            write_pi(format, pi, guard, &protection);
+        }
+        Ok(())
+    }
+
+    /// Checks the protection information of the blocks of `data` against what `protection` checks,
+    /// read from `metadata` or, with extended LBAs, the metadata interleaved in `data`.
+    /// Blocks whose protection information disables checking, as deallocated blocks may have, are skipped.
+    /// # Errors
+    /// Returns `Error::Protection` for the first field which differs, or an error if the namespace has no protection information
+    /// or the buffers don't match its blocks
+    pub fn verify_pi(
+        &self,
+        data: &[u8],
+        metadata: Option<&[u8]>,
+        protection: &Protection,
+    ) -> Result<()> {
+        let (format, blocks) = self.pi_blocks(data.len(), metadata.map(<[u8]>::len))?;
+        for block in 0..blocks {
+            let index = block as usize;
+            let data = &data[index * self.block_size as usize..][..self.block_size as usize];
+            let (data, metadata) = if self.extended_lba {
+                data.split_at(self.data_size() as usize)
+            } else {
+                let size = self.metadata_size as usize;
+                (data, &metadata.unwrap_or_default()[index * size..][..size])
+            };
+            let (guarded, pi) = metadata.split_at(pi_offset(format, metadata.len()));
+            let expected = protection.advance(format.pi_type, block);
+            check_pi(format, pi, guarded, data, &expected).map_err(
+                |(field, expected, found)| Error::Protection {
+                    block,
+                    field,
+                    expected,
+                    found,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The protection information format and amount of blocks of buffers of `data_len` and `metadata_len` bytes
+    fn pi_blocks(&self, data_len: usize, metadata_len: Option<usize>) -> Result<(PiFormat, u64)> {
+        let format = self
+            .protection
+            .ok_or_else(|| format!("namespace {} has no protection information", self.id))?;
+        if self.block_size == 0 || !(data_len as u64).is_multiple_of(self.block_size) {
+            return Err(Error::BlockSize {
+                len: data_len,
+                block_size: self.block_size,
+            });
+        }
+        let blocks = data_len as u64 / self.block_size;
+        match metadata_len {
+            None if self.extended_lba => {}
+            Some(len) if !self.extended_lba && len as u64 == blocks * self.metadata_size => {}
+            _ => {
+                return Err(format!(
+                    "metadata of {} blocks of namespace {} has to be {}",
+                    blocks,
+                    self.id,
+                    if self.extended_lba {
+                        "interleaved in the data".to_string()
+                    } else {
+                        format!("{} bytes", blocks * self.metadata_size)
+                    }
+                )
+                .into())
+            }
+        }
+        Ok((format, blocks))
+    }
+
+    /// Data and metadata of block `block` of the buffers
+    fn block<'a>(
+        &self,
+        data: &'a mut [u8],
+        metadata: Option<&'a mut [u8]>,
+        block: u64,
+    ) -> (&'a [u8], &'a mut [u8]) {
+        let index = block as usize;
+        let data = &mut data[index * self.block_size as usize..][..self.block_size as usize];
+        if self.extended_lba {
+            let (data, metadata) = data.split_at_mut(self.data_size() as usize);
+            (data, metadata)
+        } else {
+            let size = self.metadata_size as usize;
+            let metadata = metadata.unwrap_or_default();
+            (data, &mut metadata[index * size..][..size])
+        }
+    }
+}
+
+/// Offset of the protection information in the metadata of a block, the guard covers the metadata before it
+const fn pi_offset(format: PiFormat, metadata_size: usize) -> usize {
+    if format.first {
+        0
+    } else {
+        metadata_size - format.size() as usize
+    }
+}
+
+/// Guard of a block, covering its data and the metadata before its protection information
+fn guard(format: PiFormat, data: &[u8], guarded: &[u8]) -> u32 {
+    match format.guard {
+        PiGuard::Crc16 => u32::from(crc16_update(crc16_update(0, data), guarded)),
+        PiGuard::Crc32c => !crc32c_update(crc32c_update(!0, data), guarded),
+    }
+}
+
+/// Writes the big endian protection information fields of a block,
+/// the storage and reference space is 32 bits after a 16 bit guard and 80 bits after a 32 bit guard
+fn write_pi(format: PiFormat, pi: &mut [u8], guard: u32, protection: &Protection) {
+    let application_tag = protection.application_tag.to_be_bytes();
+    let tag_space = protection.tag_space(Some(format)).to_be_bytes();
+    match format.guard {
+        PiGuard::Crc16 => {
+            pi[..2].copy_from_slice(&(guard as u16).to_be_bytes());
+            pi[2..4].copy_from_slice(&application_tag);
+            pi[4..8].copy_from_slice(&tag_space[12..]);
+        }
+        PiGuard::Crc32c => {
+            pi[..4].copy_from_slice(&guard.to_be_bytes());
+            pi[4..6].copy_from_slice(&application_tag);
+            pi[6..16].copy_from_slice(&tag_space[6..]);
+        }
+    }
+}
+
+/// Checks the protection information `pi` of a block, returning the first differing field with its expected and found value
+fn check_pi(
+    format: PiFormat,
+    pi: &[u8],
+    guarded: &[u8],
+    data: &[u8],
+    expected: &Protection,
+) -> std::result::Result<(), (PiField, u64, u64)> {
+    let mut tag_space = [0; 16];
+    let (found_guard, application_tag) = match format.guard {
+        PiGuard::Crc16 => {
+            tag_space[12..].copy_from_slice(&pi[4..8]);
+            (
+                u32::from(u16::from_be_bytes([pi[0], pi[1]])),
+                u16::from_be_bytes([pi[2], pi[3]]),
+            )
+        }
+        PiGuard::Crc32c => {
+            tag_space[6..].copy_from_slice(&pi[6..16]);
+            (
+                u32::from_be_bytes([pi[0], pi[1], pi[2], pi[3]]),
+                u16::from_be_bytes([pi[4], pi[5]]),
+            )
+        }
+    };
+    let reference_mask = (1u128 << format.reference_tag_size()) - 1;
+    let reference_tag = u128::from_be_bytes(tag_space) & reference_mask;
+
+    // an all ones application tag disables checking, for type 3 only along with an all ones reference tag
+    let disabled = application_tag == 0xFFFF
+        && (format.pi_type != PiType::Type3 || reference_tag == reference_mask);
+    if disabled {
+        return Ok(());
+    }
+
+    if expected.check_guard {
+        let guard = guard(format, data, guarded);
+        if guard != found_guard {
+            return Err((PiField::Guard, guard.into(), found_guard.into()));
+        }
+    }
+    let mask = expected.application_mask;
+    if expected.check_application_tag && application_tag & mask != expected.application_tag & mask {
+        return Err((
+            PiField::ApplicationTag,
+            expected.application_tag.into(),
+            application_tag.into(),
+        ));
+    }
+    if expected.check_reference_tag
+        && format.pi_type != PiType::Type3
+        && reference_tag != truncate(expected.reference_tag, format.reference_tag_size())
+    {
+        return Err((
+            PiField::ReferenceTag,
+            expected.reference_tag,
+            reference_tag as u64,
+        ));
+    }
+    Ok(())
+}
+
+/// T10-DIF CRC16 (polynomial 0x8BB7), the guard of the 16 bit guard protection information format
+#[must_use]
+pub fn crc16_t10dif(data: &[u8]) -> u16 {
+    crc16_update(0, data)
+}
+
+/// CRC32C (Castagnoli), the guard of the 32 bit guard protection information format
+#[must_use]
+pub fn crc32c(data: &[u8]) -> u32 {
+    !crc32c_update(!0, data)
+}
+
+fn crc16_update(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |crc, &byte| {
+        (crc << 8) ^ CRC16_TABLE[usize::from((crc >> 8) as u8 ^ byte)]
+    })
+}
+
+fn crc32c_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        (crc >> 8) ^ CRC32C_TABLE[usize::from(crc as u8 ^ byte)]
+    })
+}
+
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8BB7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// reflected, as CRC32C processes the least significant bit first
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
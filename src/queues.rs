@@ -4,6 +4,7 @@ use crate::memory::Dma;
 use crate::{Result, PAGESIZE_2MIB};
 use std::hint::spin_loop;
 use std::mem;
+use std::time::{Duration, Instant};
 
 /// `NVMe` spec 4.6
 /// Completion queue entry
@@ -26,22 +27,14 @@ pub struct NvmeCompletion {
 }
 
 /// maximum amount of submission entries on a 2MiB huge page
-// pub const QUEUE_LENGTH: usize = 1024;
-// pub const QUEUE_LENGTH: usize = 65536;
-// pub const QUEUE_LENGTH: usize = PAGESIZE_2MIB / mem::size_of::<NvmeCommand>();
-pub const QUEUE_LENGTH: usize = ((PAGESIZE_2MIB / mem::size_of::<NvmeCommand>()) >> 1);
-// pub const QUEUE_LENGTH: usize = 64;
-
-// pub const QUEUE_LENGTH: usize = 65536;
-
-// static QUEUE_LENGTH: AtomicUsize =
-//     AtomicUsize::new((PAGESIZE_2MIB / mem::size_of::<NvmeCommand>()) >> 1);
+#[deprecated(
+    note = "queues are sized at runtime, pass a length up to `NvmeDevice::max_queue_len` instead"
+)]
+pub const QUEUE_LENGTH: usize = (PAGESIZE_2MIB / mem::size_of::<NvmeCommand>()) >> 1;
 
 /// Submission queue
 pub struct SubmissionQueue {
-    // TODO: switch to mempool for larger queue
-    // commands: Dma<[NvmeCommand; QUEUE_LENGTH]>,
-    pub(crate) commands: Dma<u8>,
+    pub(crate) commands: Dma<NvmeCommand>,
     pub head: usize,
     pub tail: usize,
     len: usize,
@@ -49,16 +42,21 @@ pub struct SubmissionQueue {
 }
 
 impl SubmissionQueue {
+    /// Allocates a submission queue with room for `len` entries
     pub fn new(allocator: &MemoryAccess, len: usize, doorbell: usize) -> Result<Self> {
-        let commands = allocator.allocate(mem::size_of::<NvmeCommand>() * QUEUE_LENGTH)?;
+        let commands = allocator.allocate(mem::size_of::<NvmeCommand>() * len)?;
+        Ok(Self::with_commands(commands, len, doorbell))
+    }
 
-        Ok(Self {
+    /// Submission queue with `len` entries in the already allocated memory `commands`
+    pub const fn with_commands(commands: Dma<NvmeCommand>, len: usize, doorbell: usize) -> Self {
+        Self {
             commands,
             head: 0,
             tail: 0,
-            len: len.min(QUEUE_LENGTH),
+            len,
             doorbell,
-        })
+        }
     }
 
     pub const fn is_empty(&self) -> bool {
@@ -69,6 +67,11 @@ impl SubmissionQueue {
         self.head == (self.tail + 1) % self.len
     }
 
+    /// Amount of entries that can be submitted before the queue is full
+    pub const fn free_slots(&self) -> usize {
+        self.len - 1 - (self.tail + self.len - self.head) % self.len
+    }
+
     pub fn submit_checked(&mut self, entry: NvmeCommand) -> Option<usize> {
         if self.is_full() {
             None
@@ -82,22 +85,176 @@ impl SubmissionQueue {
         // println!("SUBMISSION ENTRY: {:?}", entry);
         // self.commands[self.tail] = entry;
 
-        let ptr = self.commands.virt;
-        let array_ptr = ptr.cast::<[NvmeCommand; QUEUE_LENGTH]>();
-        (unsafe { &mut *array_ptr })[self.tail] = entry;
+        unsafe { *self.commands.virt.add(self.tail) = entry };
 
         self.tail = (self.tail + 1) % self.len;
         self.tail
     }
 
+    /// Slots of the entries submitted since the head was last updated, oldest first
+    pub fn submitted_slots(&self) -> impl Iterator<Item = usize> + '_ {
+        let submitted = (self.tail + self.len - self.head) % self.len;
+        (0..submitted).map(move |i| (self.head + i) % self.len)
+    }
+
     pub const fn get_addr(&self) -> usize {
         self.commands.phys
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+struct InFlight {
+    // command id of the first command of the request, identifies the request
+    request: u16,
+    // outstanding commands of the request, only tracked on the first command
+    outstanding: u16,
+    // first non-zero status of the request's commands, only tracked on the first command
+    status: u16,
+    // submitted command, kept for resubmission
+    command: NvmeCommand,
+    attempts: u32,
+    // time the command was last handed to the controller, `None` while it waits for resubmission
+    // or once it completed while other commands of its request are outstanding
+    submitted: Option<Instant>,
+}
+
+// generic status Command Aborted due to Failed Fused Command, without CRD, M and DNR
+const STATUS_ABORTED_FAILED_FUSED: u16 = 0x09;
+
+/// Command id allocator, keeps track of the commands in flight and the requests they belong to
+pub struct CommandIds {
+    free: Vec<u16>,
+    in_flight: Vec<Option<InFlight>>,
+}
+
+impl CommandIds {
+    pub fn new(len: usize) -> Self {
+        Self {
+            // queues hold up to 65536 entries, so every command id fits into a u16
+            free: (0..len).rev().map(|c_id| c_id as u16).collect(),
+            in_flight: vec![None; len],
+        }
+    }
+
+    /// Amount of command ids that can still be allocated
+    pub const fn available(&self) -> usize {
+        self.free.len()
+    }
+
+    /// Amount of commands in flight
+    pub const fn in_flight(&self) -> usize {
+        self.in_flight.len() - self.free.len()
+    }
+
+    /// Allocates a command id for another command of `request`, or for the first command of a new request if `None`.
+    /// The first command id of a request identifies it.
+    pub fn allocate(&mut self, request: Option<u16>) -> Option<u16> {
+        let c_id = self.free.pop()?;
+        let request = request.unwrap_or(c_id);
+        self.in_flight[c_id as usize] = Some(InFlight {
+            request,
+            outstanding: 0,
+            status: 0,
+            command: NvmeCommand::default(),
+            attempts: 0,
+            submitted: None,
+        });
+        if let Some(first) = self.in_flight[request as usize].as_mut() {
+            first.outstanding += 1;
+        }
+        Some(c_id)
+    }
+
+    /// Records `command` as submitted, counting an attempt of its command id
+    pub fn submit(&mut self, command: NvmeCommand) {
+        if let Some(in_flight) = self.in_flight[command.c_id as usize].as_mut() {
+            in_flight.command = command;
+            in_flight.attempts += 1;
+            in_flight.submitted = Some(Instant::now());
+        }
+    }
+
+    /// Stops the timeout of command `c_id` until it is submitted again
+    pub fn requeue(&mut self, c_id: u16) {
+        if let Some(in_flight) = self.in_flight[c_id as usize].as_mut() {
+            in_flight.submitted = None;
+        }
+    }
+
+    /// Restarts the timeout of command `c_id`, if it is at the controller
+    pub fn restart_timeout(&mut self, c_id: u16) {
+        if let Some(in_flight) = self.in_flight[c_id as usize].as_mut() {
+            in_flight.submitted = in_flight.submitted.map(|_| Instant::now());
+        }
+    }
+
+    /// Time the command `c_id` was last handed to the controller
+    pub fn submitted(&self, c_id: u16) -> Option<Instant> {
+        (*self.in_flight.get(c_id as usize)?)?.submitted
+    }
+
+    /// Submission time of the longest outstanding command at the controller
+    pub fn oldest_submission(&self) -> Option<Instant> {
+        self.in_flight
+            .iter()
+            .filter_map(|in_flight| in_flight.and_then(|in_flight| in_flight.submitted))
+            .min()
+    }
+
+    /// Returns the command ids and requests of the commands at the controller for longer than `timeout`
+    pub fn expired(&self, timeout: Duration) -> Vec<(u16, u16)> {
+        let now = Instant::now();
+        self.in_flight
+            .iter()
+            .enumerate()
+            .filter_map(|(c_id, in_flight)| {
+                let in_flight = (*in_flight)?;
+                let submitted = in_flight.submitted?;
+                (now.duration_since(submitted) > timeout)
+                    .then_some((c_id as u16, in_flight.request))
+            })
+            .collect()
+    }
+
+    /// Returns the submitted command `c_id` and how often it was attempted
+    pub fn command(&self, c_id: u16) -> Option<(NvmeCommand, u32)> {
+        let in_flight = (*self.in_flight.get(c_id as usize)?)?;
+        Some((in_flight.command, in_flight.attempts))
+    }
+
+    /// Marks the command `c_id` as completed with `status`.
+    /// Returns the request and its status once all of its commands completed.
+    pub fn complete(&mut self, c_id: u16, status: u16) -> Option<(u16, u16)> {
+        let command = (*self.in_flight.get(c_id as usize)?)?;
+        let first = self.in_flight[command.request as usize].as_mut()?;
+        // a command aborted because the other command of its fused operation failed carries no cause of its own
+        if first.status == 0 || first.status & 0x7FF == STATUS_ABORTED_FAILED_FUSED {
+            first.status = status;
+        }
+        first.outstanding -= 1;
+        if c_id == command.request {
+            // the first command keeps the request bookkeeping, but is no longer at the controller
+            first.submitted = None;
+        }
+        let (done, status) = (first.outstanding == 0, first.status);
+
+        if c_id != command.request {
+            self.in_flight[c_id as usize] = None;
+            self.free.push(c_id);
+        }
+        if done {
+            self.in_flight[command.request as usize] = None;
+            self.free.push(command.request);
+            Some((command.request, status))
+        } else {
+            None
+        }
+    }
+}
+
 /// Completion queue
 pub struct CompletionQueue {
-    pub(crate) commands: Dma<[NvmeCompletion; QUEUE_LENGTH]>,
+    pub(crate) commands: Dma<NvmeCompletion>,
     head: usize,
     phase: bool,
     len: usize,
@@ -106,19 +263,20 @@ pub struct CompletionQueue {
 
 // TODO: error handling
 impl CompletionQueue {
+    /// Allocates a completion queue with room for `len` entries
     pub fn new(allocator: &MemoryAccess, len: usize, doorbell: usize) -> Result<Self> {
-        let commands = allocator.allocate(mem::size_of::<NvmeCompletion>() * QUEUE_LENGTH)?;
+        let commands = allocator.allocate(mem::size_of::<NvmeCompletion>() * len)?;
         Ok(Self {
             commands,
             head: 0,
             phase: true,
-            len: len.min(QUEUE_LENGTH),
+            len,
             doorbell,
         })
     }
 
     pub fn complete(&mut self) -> Option<(usize, NvmeCompletion, usize)> {
-        let entry = &self.commands[self.head];
+        let entry = unsafe { &*self.commands.virt.add(self.head) };
 
         if ((entry.status & 1) == 1) == self.phase {
             let prev = self.head;
@@ -132,7 +290,11 @@ impl CompletionQueue {
         }
     }
 
-    pub fn complete_n(&mut self, commands: usize) -> (usize, NvmeCompletion, usize) {
+    pub fn complete_n(
+        &mut self,
+        commands: usize,
+        deadline: Option<Instant>,
+    ) -> Option<(usize, NvmeCompletion, usize)> {
         let prev = self.head;
         self.head += commands - 1;
         if self.head >= self.len {
@@ -140,14 +302,21 @@ impl CompletionQueue {
         }
         self.head %= self.len;
 
-        let (head, entry, _) = self.complete_spin();
-        (head, entry, prev)
+        let (head, entry, _) = self.complete_spin(deadline)?;
+        Some((head, entry, prev))
     }
 
-    pub fn complete_spin(&mut self) -> (usize, NvmeCompletion, usize) {
+    /// Spins until the next completion arrives, `None` if `deadline` passes first
+    pub fn complete_spin(
+        &mut self,
+        deadline: Option<Instant>,
+    ) -> Option<(usize, NvmeCompletion, usize)> {
         loop {
             if let Some(val) = self.complete() {
-                return val;
+                return Some(val);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                return None;
             }
             spin_loop();
         }
@@ -157,3 +326,51 @@ impl CompletionQueue {
         self.commands.phys
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submit(c_ids: &mut CommandIds, c_id: u16) {
+        c_ids.submit(NvmeCommand {
+            c_id,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn split_request_with_early_first_completion() {
+        let mut c_ids = CommandIds::new(4);
+        let first = c_ids.allocate(None).unwrap();
+        let second = c_ids.allocate(Some(first)).unwrap();
+        submit(&mut c_ids, first);
+        submit(&mut c_ids, second);
+
+        assert_eq!(c_ids.complete(first, 0), None);
+        // only the second command is still at the controller
+        assert_eq!(c_ids.submitted(first), None);
+        assert_eq!(c_ids.oldest_submission(), c_ids.submitted(second));
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(
+            c_ids.expired(Duration::from_millis(1)),
+            vec![(second, first)]
+        );
+
+        assert_eq!(c_ids.complete(second, 0), Some((first, 0)));
+        assert_eq!(c_ids.in_flight(), 0);
+        assert_eq!(c_ids.oldest_submission(), None);
+    }
+
+    #[test]
+    fn split_request_keeps_first_failure() {
+        let mut c_ids = CommandIds::new(4);
+        let first = c_ids.allocate(None).unwrap();
+        let second = c_ids.allocate(Some(first)).unwrap();
+        submit(&mut c_ids, first);
+        submit(&mut c_ids, second);
+
+        assert_eq!(c_ids.complete(second, 0x281), None);
+        assert_eq!(c_ids.complete(first, 0), Some((first, 0x281)));
+        assert_eq!(c_ids.available(), 4);
+    }
+}
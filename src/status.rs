@@ -0,0 +1,263 @@
+use std::fmt;
+
+/// Defines a status code enum, codes without a variant are kept in `Other`
+macro_rules! status_codes {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident = $value:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($variant,)*
+            Other(u8),
+        }
+
+        impl From<u8> for $name {
+            fn from(value: u8) -> Self {
+                match value {
+                    $($value => Self::$variant,)*
+                    other => Self::Other(other),
+                }
+            }
+        }
+    };
+}
+
+/// `NVMe` spec 4.6.1.2
+/// Status code type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCodeType {
+    Generic,
+    CommandSpecific,
+    MediaError,
+    PathRelated,
+    VendorSpecific,
+    Reserved(u8),
+}
+
+impl From<u8> for StatusCodeType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Generic,
+            1 => Self::CommandSpecific,
+            2 => Self::MediaError,
+            3 => Self::PathRelated,
+            7 => Self::VendorSpecific,
+            other => Self::Reserved(other),
+        }
+    }
+}
+
+status_codes!(
+    /// `NVMe` spec 4.6.1.2.1
+    /// Generic command status values, including the NVM command set specific ones
+    GenericStatus {
+        Success = 0x00,
+        InvalidCommandOpcode = 0x01,
+        InvalidFieldInCommand = 0x02,
+        CommandIdConflict = 0x03,
+        DataTransferError = 0x04,
+        AbortedPowerLoss = 0x05,
+        InternalError = 0x06,
+        AbortRequested = 0x07,
+        AbortedSqDeletion = 0x08,
+        AbortedFailedFusedCommand = 0x09,
+        AbortedMissingFusedCommand = 0x0A,
+        InvalidNamespaceOrFormat = 0x0B,
+        CommandSequenceError = 0x0C,
+        InvalidSglSegmentDescriptor = 0x0D,
+        InvalidNumberOfSglDescriptors = 0x0E,
+        DataSglLengthInvalid = 0x0F,
+        MetadataSglLengthInvalid = 0x10,
+        SglDescriptorTypeInvalid = 0x11,
+        InvalidUseOfControllerMemoryBuffer = 0x12,
+        PrpOffsetInvalid = 0x13,
+        AtomicWriteUnitExceeded = 0x14,
+        OperationDenied = 0x15,
+        SglOffsetInvalid = 0x16,
+        HostIdentifierInconsistentFormat = 0x18,
+        KeepAliveTimerExpired = 0x19,
+        KeepAliveTimeoutInvalid = 0x1A,
+        AbortedPreemptAndAbort = 0x1B,
+        SanitizeFailed = 0x1C,
+        SanitizeInProgress = 0x1D,
+        SglDataBlockGranularityInvalid = 0x1E,
+        CommandNotSupportedForQueueInCmb = 0x1F,
+        NamespaceIsWriteProtected = 0x20,
+        CommandInterrupted = 0x21,
+        TransientTransportError = 0x22,
+        LbaOutOfRange = 0x80,
+        CapacityExceeded = 0x81,
+        NamespaceNotReady = 0x82,
+        ReservationConflict = 0x83,
+        FormatInProgress = 0x84,
+    }
+);
+
+status_codes!(
+    /// `NVMe` spec 4.6.1.2.2
+    /// Command specific status values, including the NVM command set specific ones
+    CommandSpecificStatus {
+        CompletionQueueInvalid = 0x00,
+        InvalidQueueIdentifier = 0x01,
+        InvalidQueueSize = 0x02,
+        AbortCommandLimitExceeded = 0x03,
+        AsyncEventRequestLimitExceeded = 0x05,
+        InvalidFirmwareSlot = 0x06,
+        InvalidFirmwareImage = 0x07,
+        InvalidInterruptVector = 0x08,
+        InvalidLogPage = 0x09,
+        InvalidFormat = 0x0A,
+        FirmwareActivationRequiresConventionalReset = 0x0B,
+        InvalidQueueDeletion = 0x0C,
+        FeatureIdentifierNotSaveable = 0x0D,
+        FeatureNotChangeable = 0x0E,
+        FeatureNotNamespaceSpecific = 0x0F,
+        FirmwareActivationRequiresSubsystemReset = 0x10,
+        FirmwareActivationRequiresControllerReset = 0x11,
+        FirmwareActivationRequiresMaximumTimeViolation = 0x12,
+        FirmwareActivationProhibited = 0x13,
+        OverlappingRange = 0x14,
+        NamespaceInsufficientCapacity = 0x15,
+        NamespaceIdentifierUnavailable = 0x16,
+        NamespaceAlreadyAttached = 0x18,
+        NamespaceIsPrivate = 0x19,
+        NamespaceNotAttached = 0x1A,
+        ThinProvisioningNotSupported = 0x1B,
+        ControllerListInvalid = 0x1C,
+        ConflictingAttributes = 0x80,
+        InvalidProtectionInformation = 0x81,
+        AttemptedWriteToReadOnlyRange = 0x82,
+        CommandSizeLimitExceeded = 0x83,
+    }
+);
+
+status_codes!(
+    /// `NVMe` spec 4.6.1.2.3
+    /// Media and data integrity error values
+    MediaErrorStatus {
+        WriteFault = 0x80,
+        UnrecoveredReadError = 0x81,
+        EndToEndGuardCheckError = 0x82,
+        EndToEndApplicationTagCheckError = 0x83,
+        EndToEndReferenceTagCheckError = 0x84,
+        CompareFailure = 0x85,
+        AccessDenied = 0x86,
+        DeallocatedOrUnwrittenLogicalBlock = 0x87,
+    }
+);
+
+status_codes!(
+    /// `NVMe` spec 4.6.1.2.4
+    /// Path related status values
+    PathStatus {
+        InternalPathError = 0x00,
+        AsymmetricAccessPersistentLoss = 0x01,
+        AsymmetricAccessInaccessible = 0x02,
+        AsymmetricAccessTransition = 0x03,
+        ControllerPathingError = 0x60,
+        HostPathingError = 0x70,
+        AbortedByHost = 0x71,
+    }
+);
+
+/// Status code, interpreted according to its status code type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCode {
+    Generic(GenericStatus),
+    CommandSpecific(CommandSpecificStatus),
+    MediaError(MediaErrorStatus),
+    PathRelated(PathStatus),
+    VendorSpecific(u8),
+    Reserved(u8),
+}
+
+/// `NVMe` spec 4.6.1.2
+/// Decoded status field of a completion queue entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NvmeStatus {
+    /// Status code type
+    pub sct: StatusCodeType,
+    /// Status code
+    pub sc: StatusCode,
+    /// Command retry delay, selects CRDT1-3 of identify controller, 0 if the command can be retried immediately
+    pub crd: u8,
+    /// More status information is available in the error information log page
+    pub more: bool,
+    /// Do not retry
+    pub dnr: bool,
+}
+
+impl NvmeStatus {
+    pub const SUCCESS: Self = Self {
+        sct: StatusCodeType::Generic,
+        sc: StatusCode::Generic(GenericStatus::Success),
+        crd: 0,
+        more: false,
+        dnr: false,
+    };
+
+    /// Decodes the status field of a completion queue entry, without its phase tag
+    #[must_use]
+    pub fn new(status: u16) -> Self {
+        let code = (status & 0xFF) as u8;
+        let sct = StatusCodeType::from(((status >> 8) & 0x7) as u8);
+        let sc = match sct {
+            StatusCodeType::Generic => StatusCode::Generic(code.into()),
+            StatusCodeType::CommandSpecific => StatusCode::CommandSpecific(code.into()),
+            StatusCodeType::MediaError => StatusCode::MediaError(code.into()),
+            StatusCodeType::PathRelated => StatusCode::PathRelated(code.into()),
+            StatusCodeType::VendorSpecific => StatusCode::VendorSpecific(code),
+            StatusCodeType::Reserved(_) => StatusCode::Reserved(code),
+        };
+
+        Self {
+            sct,
+            sc,
+            crd: ((status >> 11) & 0x3) as u8,
+            more: (status >> 13) & 1 == 1,
+            dnr: (status >> 14) & 1 == 1,
+        }
+    }
+
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.sc == StatusCode::Generic(GenericStatus::Success)
+    }
+
+    /// Whether a compare found different data on the device
+    #[must_use]
+    pub fn is_compare_failure(&self) -> bool {
+        self.sc == StatusCode::MediaError(MediaErrorStatus::CompareFailure)
+    }
+
+    /// Whether the failure is transient and the command may be retried
+    #[must_use]
+    pub const fn is_retryable(&self) -> bool {
+        !self.dnr
+            && matches!(
+                self.sc,
+                StatusCode::Generic(
+                    GenericStatus::NamespaceNotReady
+                        | GenericStatus::CommandInterrupted
+                        | GenericStatus::TransientTransportError
+                ) | StatusCode::PathRelated(
+                    PathStatus::InternalPathError | PathStatus::AsymmetricAccessTransition
+                )
+            )
+    }
+}
+
+impl fmt::Display for NvmeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.sc)?;
+        if self.crd != 0 {
+            write!(f, ", CRD: {}", self.crd)?;
+        }
+        if self.more {
+            write!(f, ", More")?;
+        }
+        if self.dnr {
+            write!(f, ", DNR")?;
+        }
+        Ok(())
+    }
+}
@@ -10,9 +10,9 @@ use crate::{
 use std::fmt::Display;
 use std::fs;
 use std::fs::{File, OpenOptions};
-use std::mem;
+use std::{io, mem};
 
-use std::os::unix::io::{IntoRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::path::Path;
 use std::ptr;
 use std::sync::atomic::{AtomicU8, Ordering};
@@ -58,6 +58,7 @@ impl Vfio {
     // from enum in vfio.h
     pub const VFIO_PCI_CONFIG_REGION_INDEX: u32 = 7;
     pub const VFIO_PCI_BAR0_REGION_INDEX: u32 = 0;
+    pub const VFIO_PCI_MSIX_IRQ_INDEX: u32 = 2;
 
     // Intel VTd consts
     // constants to determine IOMMU (guest) address width
@@ -364,6 +365,59 @@ impl Vfio {
         Ok((addr, len))
     }
 
+    /// Returns the amount of MSI-X vectors of the device.
+    /// # Errors
+    pub fn msix_vectors(&self) -> Result<u32> {
+        let mut irq_info = vfio_irq_info {
+            argsz: mem::size_of::<vfio_irq_info>() as u32,
+            flags: 0,
+            index: Self::VFIO_PCI_MSIX_IRQ_INDEX,
+            count: 0,
+        };
+
+        ioctl_unsafe!(
+            self.device_fd,
+            IoctlOp::VFIO_DEVICE_GET_IRQ_INFO,
+            &mut irq_info
+        )?;
+
+        Ok(irq_info.count)
+    }
+
+    /// Enables the first `vectors` MSI-X vectors of the device, each one signals its own eventfd.
+    /// Returns the eventfds, indexed by vector, they are closed once dropped.
+    /// # Errors
+    pub fn enable_msix(&self, vectors: u32) -> Result<Vec<OwnedFd>> {
+        let eventfds = (0..vectors)
+            .map(|_| {
+                let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+                if fd == -1 {
+                    Err(Error::Io(io::Error::last_os_error()))
+                } else {
+                    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+                }
+            })
+            .collect::<Result<Vec<OwnedFd>>>()?;
+
+        // struct vfio_irq_set, followed by one eventfd per vector
+        let mut irq_set: Vec<u32> = vec![
+            (mem::size_of::<vfio_irq_set<[RawFd; 0]>>() + mem::size_of_val(&eventfds[..])) as u32,
+            IoctlFlag::VFIO_IRQ_SET_DATA_EVENTFD | IoctlFlag::VFIO_IRQ_SET_ACTION_TRIGGER,
+            Self::VFIO_PCI_MSIX_IRQ_INDEX,
+            0,
+            vectors,
+        ];
+        irq_set.extend(eventfds.iter().map(|fd| fd.as_raw_fd() as u32));
+
+        ioctl_unsafe!(
+            self.device_fd,
+            IoctlOp::VFIO_DEVICE_SET_IRQS,
+            irq_set.as_ptr()
+        )?;
+
+        Ok(eventfds)
+    }
+
     /// Checks if the IOMMU is from Intel.
     #[allow(clippy::must_use_candidate)]
     pub fn is_intel_iommu(pci_addr: &str) -> bool {
@@ -0,0 +1,69 @@
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use vroom::memory::Dma;
+use vroom::{AsyncQueuePair, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+struct CountingWaker(AtomicUsize);
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// polls the queue pair until the future was woken, which only happens once its request completed
+fn block_on<F: Future>(qpair: &AsyncQueuePair, future: F) -> F::Output {
+    let mut future = pin!(future);
+    let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = Waker::from(Arc::clone(&wakes));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        let woken = wakes.0.load(Ordering::Relaxed);
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        while wakes.0.load(Ordering::Relaxed) == woken {
+            qpair.poll();
+        }
+    }
+}
+
+#[test]
+pub fn async_read_write() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+
+    let qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+    let qpair = AsyncQueuePair::new(qpair);
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    let rand_block = &(0..buffer.size)
+        .map(|_| rand::random::<u8>())
+        .collect::<Vec<_>>()[..];
+    buffer[..rand_block.len()].copy_from_slice(rand_block);
+
+    block_on(&qpair, qpair.write(&ns, 0, &buffer)).unwrap();
+
+    buffer[..rand_block.len()].fill(0);
+    block_on(&qpair, qpair.read(&ns, 0, &mut buffer)).unwrap();
+
+    assert_eq!(
+        rand_block,
+        &buffer[..rand_block.len()],
+        "Data read from NVMe does not match expected data"
+    );
+
+    let qpair = qpair.into_inner().unwrap();
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
@@ -0,0 +1,39 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{Error, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn reject_partial_blocks() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let block_size = ns.block_size as usize;
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let buffer: Dma<u8> = allocate_dma_buffer(&nvme, 2 * PAGESIZE_4KIB);
+
+    let partial = qpair.submit_io(&ns, &buffer.slice(0..block_size - 1), 0, false);
+    assert!(matches!(partial, Err(Error::BlockSize { .. })));
+    let misaligned = qpair.submit_io(&ns, &buffer.slice(1..block_size + 1), 0, false);
+    assert!(matches!(misaligned, Err(Error::Misaligned { .. })));
+    assert_eq!(qpair.outstanding(), 0);
+
+    qpair
+        .submit_io(&ns, &buffer.slice(0..block_size), 0, false)
+        .expect("queue full");
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+
+    assert!(matches!(
+        nvme.read(&ns, &buffer.slice(0..block_size + 1), 0),
+        Err(Error::BlockSize { .. })
+    ));
+    nvme.read(&ns, &buffer.slice(0..block_size), 0).unwrap();
+}
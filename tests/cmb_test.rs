@@ -0,0 +1,114 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{Mapping, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn cmb_submission_queue() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    if let Err(e) = nvme
+        .enable_cmb()
+        .and_then(|()| nvme.set_cmb_submission_queues(true))
+    {
+        eprintln!(
+            "Skipping, submission queues can't be placed in the CMB: {}",
+            e
+        );
+        return;
+    }
+
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    let rand_block = &(0..PAGESIZE_4KIB)
+        .map(|_| rand::random::<u8>())
+        .collect::<Vec<_>>()[..];
+    buffer[..PAGESIZE_4KIB].copy_from_slice(rand_block);
+
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, true)
+        .expect("queue full");
+    assert!(
+        qpair.complete_io(1).unwrap()[0].is_success(),
+        "IO Completion failed!"
+    );
+
+    buffer[..PAGESIZE_4KIB].fill(0);
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, false)
+        .expect("queue full");
+    assert!(
+        qpair.complete_io(1).unwrap()[0].is_success(),
+        "IO Completion failed!"
+    );
+
+    assert_eq!(
+        rand_block,
+        &buffer[..PAGESIZE_4KIB],
+        "Data read from NVMe does not match expected data"
+    );
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
+
+#[test]
+pub fn cmb_data_buffer() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    if let Err(e) = nvme.enable_cmb() {
+        eprintln!("Skipping, CMB can't be enabled: {}", e);
+        return;
+    }
+    let cmb = nvme.cmb().unwrap();
+    if !cmb.supports_read_data() || !cmb.supports_write_data() {
+        eprintln!("Skipping, CMB does not support data buffers");
+        return;
+    }
+    let mut cmb_buffer: Dma<u8> = cmb.allocate(PAGESIZE_4KIB).unwrap();
+
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let rand_block = &(0..PAGESIZE_4KIB)
+        .map(|_| rand::random::<u8>())
+        .collect::<Vec<_>>()[..];
+    cmb_buffer[..PAGESIZE_4KIB].copy_from_slice(rand_block);
+
+    // write from controller memory, read back into host memory
+    qpair
+        .submit_io(&ns, &cmb_buffer.slice(0..PAGESIZE_4KIB), 0, true)
+        .expect("queue full");
+    assert!(
+        qpair.complete_io(1).unwrap()[0].is_success(),
+        "IO Completion failed!"
+    );
+
+    let buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, false)
+        .expect("queue full");
+    assert!(
+        qpair.complete_io(1).unwrap()[0].is_success(),
+        "IO Completion failed!"
+    );
+
+    assert_eq!(
+        rand_block,
+        &buffer[..PAGESIZE_4KIB],
+        "Data read from NVMe does not match expected data"
+    );
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+    nvme.cmb().unwrap().deallocate(&cmb_buffer).unwrap();
+}
@@ -22,17 +22,3 @@ pub fn allocate_dma_buffer(nvme: &NvmeDevice, size: usize) -> Dma<u8> {
         process::exit(1);
     })
 }
-
-pub fn nvme_write(nvme: &mut NvmeDevice, buffer: &Dma<u8>, lba: u64) {
-    nvme.write(buffer, lba).unwrap_or_else(|e| {
-        eprintln!("NVMe write failed: {}", e);
-        process::exit(1);
-    });
-}
-
-pub fn nvme_read(nvme: &mut NvmeDevice, buffer: &mut Dma<u8>, lba: u64) {
-    nvme.read(buffer, lba).unwrap_or_else(|e| {
-        eprintln!("NVMe read failed: {}", e);
-        process::exit(1);
-    });
-}
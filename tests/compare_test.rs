@@ -0,0 +1,55 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{Error, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn compare_and_write() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut old: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    let mut new: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    old[..PAGESIZE_4KIB].fill(0xAA);
+    new[..PAGESIZE_4KIB].fill(0x55);
+    let old = old.slice(0..PAGESIZE_4KIB);
+    let new = new.slice(0..PAGESIZE_4KIB);
+
+    qpair.submit_io(&ns, &old, 0, true).expect("queue full");
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    if let Err(e) = qpair.submit_compare(&ns, &old, 0) {
+        eprintln!("Skipping, Compare not supported: {}", e);
+        nvme.delete_io_queue_pair(&qpair).unwrap();
+        return;
+    }
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    qpair.submit_compare(&ns, &new, 0).unwrap();
+    let completed = qpair.complete_io(1).unwrap();
+    assert!(matches!(completed[0].result(), Err(Error::CompareFailure)));
+
+    if let Err(e) = qpair.submit_compare_and_write(&ns, &old, &new, 0) {
+        eprintln!("Skipping, fused Compare and Write not supported: {}", e);
+        nvme.delete_io_queue_pair(&qpair).unwrap();
+        return;
+    }
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    // the blocks now hold `new`, so comparing with `old` fails and nothing is written
+    qpair.submit_compare_and_write(&ns, &old, &old, 0).unwrap();
+    let completed = qpair.complete_io(1).unwrap();
+    assert!(matches!(completed[0].result(), Err(Error::CompareFailure)));
+
+    qpair.submit_compare(&ns, &new, 0).unwrap();
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
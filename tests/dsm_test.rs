@@ -0,0 +1,44 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{DsmAttributes, DsmRange, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn deallocate_ranges() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, true)
+        .expect("queue full");
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    let ranges = [DsmRange::new(0, 8), DsmRange::new(64, 8)];
+    if let Err(e) = qpair.submit_dataset_management(&ns, &ranges, DsmAttributes::DEALLOCATE) {
+        eprintln!("Skipping, Dataset Management not supported: {}", e);
+        nvme.delete_io_queue_pair(&qpair).unwrap();
+        return;
+    }
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    assert!(qpair
+        .submit_dataset_management(&ns, &[], DsmAttributes::DEALLOCATE)
+        .is_err());
+    let too_many = vec![DsmRange::new(0, 1); DsmRange::MAX_RANGES + 1];
+    assert!(qpair
+        .submit_dataset_management(&ns, &too_many, DsmAttributes::DEALLOCATE)
+        .is_err());
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+
+    nvme.dataset_management(&ns, &ranges, DsmAttributes::DEALLOCATE)
+        .unwrap();
+}
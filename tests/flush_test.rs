@@ -0,0 +1,36 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::PAGESIZE_4KIB;
+
+mod common;
+use common::*;
+
+#[test]
+pub fn fua_write_and_flush() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    buffer[..PAGESIZE_4KIB].fill(0xAB);
+
+    qpair
+        .submit_write_fua(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0)
+        .expect("queue full");
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    match qpair.flush(&ns).unwrap() {
+        Some(_) => qpair.complete_io(1).unwrap()[0].result().unwrap(),
+        None => assert!(!nvme.volatile_write_cache()),
+    }
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+
+    nvme.write_fua(&ns, &buffer.slice(0..PAGESIZE_4KIB), 8)
+        .unwrap();
+    nvme.flush(&ns).unwrap();
+}
@@ -0,0 +1,56 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{PollStrategy, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn spin_then_sleep_read_write() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+    qpair.poll_strategy = PollStrategy::SpinThenSleep;
+    assert!(qpair.latency_estimate().is_none());
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    let rand_block = &(0..PAGESIZE_4KIB)
+        .map(|_| rand::random::<u8>())
+        .collect::<Vec<_>>()[..];
+    buffer[..PAGESIZE_4KIB].copy_from_slice(rand_block);
+
+    let request = qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, true)
+        .expect("queue full");
+    let completed = qpair.complete_io(1).unwrap();
+    assert_eq!(completed[0].request, request);
+    assert!(completed[0].is_success(), "IO Completion failed!");
+    let estimate = qpair
+        .latency_estimate()
+        .expect("no latency estimate after a completion");
+    assert!(!estimate.is_zero());
+
+    buffer[..PAGESIZE_4KIB].fill(0);
+
+    // the second command already sleeps for part of the estimated latency
+    let request = qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, false)
+        .expect("queue full");
+    let completed = qpair.complete_io(1).unwrap();
+    assert_eq!(completed[0].request, request);
+    assert!(completed[0].is_success(), "IO Completion failed!");
+    assert!(qpair.latency_estimate().is_some());
+
+    assert_eq!(
+        rand_block,
+        &buffer[..PAGESIZE_4KIB],
+        "Data read from NVMe does not match expected data"
+    );
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
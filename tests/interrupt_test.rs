@@ -0,0 +1,52 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::PAGESIZE_4KIB;
+
+mod common;
+use common::*;
+
+#[test]
+pub fn interrupt_read_write() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    nvme.enable_interrupts().unwrap_or_else(|e| {
+        eprintln!("Enabling interrupts failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    let rand_block = &(0..PAGESIZE_4KIB)
+        .map(|_| rand::random::<u8>())
+        .collect::<Vec<_>>()[..];
+    buffer[..PAGESIZE_4KIB].copy_from_slice(rand_block);
+
+    let request = qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, true)
+        .expect("queue full");
+    let completed = qpair.wait_for_completion().unwrap();
+    assert_eq!(completed.request, request);
+    assert!(completed.is_success(), "IO Completion failed!");
+
+    buffer[..PAGESIZE_4KIB].fill(0);
+
+    let request = qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, false)
+        .expect("queue full");
+    let completed = qpair.wait_for_completion().unwrap();
+    assert_eq!(completed.request, request);
+    assert!(completed.is_success(), "IO Completion failed!");
+
+    assert_eq!(
+        rand_block,
+        &buffer[..PAGESIZE_4KIB],
+        "Data read from NVMe does not match expected data"
+    );
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
@@ -0,0 +1,61 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::PAGESIZE_2MIB;
+
+mod common;
+use common::*;
+
+#[test]
+pub fn large_read_write() {
+    let pci_addr = &get_pci_addr();
+
+    let lba = 0;
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+
+    let queue_len = nvme.max_queue_len();
+    let mut qpair = nvme.create_io_queue_pair(queue_len).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    // up to 64 MiB, split into as many commands as the queue holds
+    let max_bytes = (queue_len - 1) * nvme.max_transfer_size();
+    let bytes = (32 * PAGESIZE_2MIB).min(max_bytes / PAGESIZE_2MIB * PAGESIZE_2MIB);
+    assert!(bytes > nvme.max_transfer_size());
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, bytes);
+    let rand_block = &(0..bytes).map(|_| rand::random::<u8>()).collect::<Vec<_>>()[..];
+    buffer[..bytes].copy_from_slice(rand_block);
+
+    let request = qpair
+        .submit_io(&ns, &buffer.slice(0..bytes), lba, true)
+        .expect("queue full");
+    let completed = qpair.complete_io(1).unwrap();
+    assert_eq!(completed[0].request, request);
+    assert!(completed[0].is_success(), "IO Completion failed!");
+
+    buffer[..bytes].fill(0);
+
+    let request = qpair
+        .submit_io(&ns, &buffer.slice(0..bytes), lba, false)
+        .expect("queue full");
+    let completed = qpair.complete_io(1).unwrap();
+    assert_eq!(completed[0].request, request);
+    assert!(completed[0].is_success(), "IO Completion failed!");
+
+    assert_eq!(
+        rand_block,
+        &buffer[..bytes],
+        "Data read from NVMe does not match expected data"
+    );
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+
+    // the device functions split through the device buffer as well
+    let data = &rand_block[..2 * PAGESIZE_2MIB];
+    nvme.batched_write(&ns, data, lba, 4).unwrap();
+    let mut read = vec![0u8; data.len()];
+    nvme.batched_read(&ns, &mut read, lba, 4).unwrap();
+    assert_eq!(data, &read[..]);
+}
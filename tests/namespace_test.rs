@@ -0,0 +1,53 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{Error, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn io_per_namespace() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let mut ids = nvme.namespaces.keys().copied().collect::<Vec<_>>();
+    ids.sort_unstable();
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, 2 * PAGESIZE_4KIB);
+    for &id in &ids {
+        let ns = nvme.namespace(id).unwrap();
+        let bytes = ns.block_size as usize;
+        buffer[..bytes].fill(id as u8);
+
+        let last = ns.blocks - 1;
+        qpair
+            .submit_io(&ns, &buffer.slice(0..bytes), last, true)
+            .expect("queue full");
+        qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+        buffer[..bytes].fill(0);
+        qpair
+            .submit_io(&ns, &buffer.slice(0..bytes), last, false)
+            .expect("queue full");
+        qpair.complete_io(1).unwrap()[0].result().unwrap();
+        assert!(buffer[..bytes].iter().all(|&b| b == id as u8));
+
+        // the block past the end of the namespace belongs to no one
+        let past_end = qpair.submit_io(&ns, &buffer.slice(0..bytes), ns.blocks, true);
+        assert!(matches!(past_end, Err(Error::OutOfRange { .. })));
+        let crossing = qpair.submit_io(&ns, &buffer.slice(0..2 * bytes), last, true);
+        assert!(matches!(crossing, Err(Error::OutOfRange { .. })));
+        assert!(matches!(
+            nvme.write(&ns, &buffer.slice(0..bytes), ns.blocks),
+            Err(Error::OutOfRange { .. })
+        ));
+    }
+    assert_eq!(qpair.outstanding(), 0);
+
+    assert!(nvme.namespace(0).is_err());
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
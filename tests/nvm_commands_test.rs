@@ -0,0 +1,105 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{CopyRange, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn verify() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    match qpair.submit_verify(&ns, 0, 64) {
+        Ok(_) => qpair.complete_io(1).unwrap()[0].result().unwrap(),
+        Err(e) => eprintln!("Skipping, Verify not supported: {}", e),
+    }
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
+
+#[test]
+pub fn copy_ranges() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    buffer[..PAGESIZE_4KIB].fill(0x5A);
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, true)
+        .expect("queue full");
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    let ranges = [CopyRange::new(0, blocks as u32)];
+    if let Err(e) = qpair.submit_copy(&ns, &ranges, 4 * blocks) {
+        eprintln!("Skipping, Copy not supported: {}", e);
+        nvme.delete_io_queue_pair(&qpair).unwrap();
+        return;
+    }
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    buffer[..PAGESIZE_4KIB].fill(0);
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 4 * blocks, false)
+        .expect("queue full");
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+    assert!(
+        buffer[..PAGESIZE_4KIB].iter().all(|&b| b == 0x5A),
+        "Blocks were not copied"
+    );
+
+    assert!(qpair.submit_copy(&ns, &[], 0).is_err());
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
+
+#[test]
+pub fn write_uncorrectable() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    let lba = 16 * PAGESIZE_4KIB as u64 / ns.block_size;
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+
+    if let Err(e) = qpair.submit_write_uncorrectable(&ns, lba, blocks) {
+        eprintln!("Skipping, Write Uncorrectable not supported: {}", e);
+        nvme.delete_io_queue_pair(&qpair).unwrap();
+        return;
+    }
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), lba, false)
+        .expect("queue full");
+    assert!(
+        qpair.complete_io(1).unwrap()[0].result().is_err(),
+        "Read of uncorrectable blocks succeeded"
+    );
+
+    // writing the blocks makes them readable again
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), lba, true)
+        .expect("queue full");
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
@@ -0,0 +1,28 @@
+#[allow(dead_code)]
+mod common;
+use common::*;
+
+#[test]
+pub fn pmr_write_persist_read() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    if let Err(e) = nvme.enable_pmr() {
+        eprintln!("Skipping, PMR can't be enabled: {}", e);
+        return;
+    }
+    let pmr = nvme.pmr_mut().unwrap();
+
+    let data = (0..512).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+    pmr.write(0, &data).unwrap();
+    pmr.persist().unwrap();
+
+    let mut read = vec![0; data.len()];
+    pmr.read(0, &mut read).unwrap();
+    assert_eq!(
+        data, read,
+        "Data read from PMR does not match expected data"
+    );
+
+    assert!(pmr.write(pmr.size(), &data).is_err());
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+use vroom::memory::{Dma, DmaSlice};
+use vroom::PAGESIZE_4KIB;
+
+mod common;
+use common::*;
+
+#[test]
+pub fn poll_timer_read_write() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+    assert!(qpair.completion_fd().is_none());
+    let fd = qpair.enable_poll_timer(Duration::from_micros(100)).unwrap();
+    assert_eq!(qpair.completion_fd(), Some(fd));
+
+    let blocks = 4;
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, blocks * PAGESIZE_4KIB);
+    let rand_block = &(0..blocks * PAGESIZE_4KIB)
+        .map(|_| rand::random::<u8>())
+        .collect::<Vec<_>>()[..];
+    buffer[..blocks * PAGESIZE_4KIB].copy_from_slice(rand_block);
+
+    let lba_step = PAGESIZE_4KIB as u64 / ns.block_size;
+    for write in [true, false] {
+        if !write {
+            buffer[..blocks * PAGESIZE_4KIB].fill(0);
+        }
+        let mut requests = (0..blocks)
+            .map(|i| {
+                let offset = i * PAGESIZE_4KIB;
+                qpair
+                    .submit_io(
+                        &ns,
+                        &buffer.slice(offset..offset + PAGESIZE_4KIB),
+                        i as u64 * lba_step,
+                        write,
+                    )
+                    .expect("queue full")
+            })
+            .collect::<Vec<_>>();
+
+        // wait for the timer like an event loop would, then drain whatever finished
+        while !requests.is_empty() {
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pollfd, 1, 1000) };
+            assert_eq!(ready, 1, "completion fd did not become readable");
+
+            for completed in qpair.poll_completions().unwrap() {
+                assert!(completed.is_success(), "IO Completion failed!");
+                let position = requests
+                    .iter()
+                    .position(|&request| request == completed.request)
+                    .expect("completed request was not submitted");
+                requests.swap_remove(position);
+            }
+        }
+        assert_eq!(qpair.outstanding(), 0);
+    }
+
+    assert_eq!(
+        rand_block,
+        &buffer[..blocks * PAGESIZE_4KIB],
+        "Data read from NVMe does not match expected data"
+    );
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
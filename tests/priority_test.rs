@@ -0,0 +1,41 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{QueuePriority, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn queue_priorities() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    if !nvme.weighted_round_robin() {
+        eprintln!(
+            "Controller does not support weighted round robin arbitration, priorities are ignored"
+        );
+    }
+
+    let buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    for priority in [
+        QueuePriority::Urgent,
+        QueuePriority::High,
+        QueuePriority::Medium,
+        QueuePriority::Low,
+    ] {
+        let mut qpair = nvme
+            .create_io_queue_pair_with_priority(64, priority)
+            .unwrap_or_else(|e| {
+                eprintln!("Creation of {:?} IO Queue Pair failed: {}", priority, e);
+                std::process::exit(1);
+            });
+
+        qpair
+            .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, false)
+            .expect("queue full");
+        let completed = qpair.complete_io(1).unwrap();
+        assert!(completed[0].is_success(), "IO Completion failed!");
+
+        nvme.delete_io_queue_pair(&qpair).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{crc16_t10dif, crc32c, Error, PiField, PiFormat, PiGuard, PiType, Protection};
+use vroom::{NvmeNamespace, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn guard_check_values() {
+    assert_eq!(crc16_t10dif(b"123456789"), 0xD0DB);
+    assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+}
+
+#[test]
+pub fn host_generate_and_verify() {
+    // reference tags of LBAs beyond 32 bits only fit the 32 bit guard format
+    let high_lba = (1 << 40) + 16;
+    for (guard, extended_lba, first, storage_tag_size, lba) in [
+        (PiGuard::Crc16, false, false, 0, 16),
+        (PiGuard::Crc16, true, false, 0, 16),
+        (PiGuard::Crc32c, true, true, 0, 16),
+        (PiGuard::Crc32c, false, true, 16, 16),
+        (PiGuard::Crc32c, false, false, 16, high_lba),
+        (PiGuard::Crc32c, true, false, 32, high_lba),
+    ] {
+        let ns = NvmeNamespace {
+            id: 1,
+            blocks: 1024,
+            block_size: if extended_lba { 512 + 16 } else { 512 },
+            metadata_size: 16,
+            extended_lba,
+            protection: Some(PiFormat {
+                pi_type: PiType::Type1,
+                guard,
+                first,
+                storage_tag_size,
+            }),
+            max_copy_range_blocks: 0,
+            max_copy_blocks: 0,
+            max_copy_ranges: 1,
+        };
+        let blocks = 4;
+        let mut data: Vec<u8> = (0..blocks * ns.block_size)
+            .map(|_| rand::random::<u8>())
+            .collect();
+        let mut metadata = vec![0u8; (blocks * ns.metadata_size) as usize];
+        let mut metadata = (!extended_lba).then_some(&mut metadata[..]);
+
+        let protection = Protection {
+            storage_tag: 0xABCD,
+            ..Protection::check_all(lba, 0x1234)
+        };
+        ns.generate_pi(&mut data, metadata.as_deref_mut(), &protection)
+            .unwrap();
+        ns.verify_pi(&data, metadata.as_deref(), &protection)
+            .unwrap();
+
+        // the reference tags of type 1 follow the LBAs, all of their bits
+        if lba == high_lba {
+            let truncated = Protection::check_all(lba as u32 as u64, 0x1234);
+            assert!(matches!(
+                ns.verify_pi(&data, metadata.as_deref(), &truncated),
+                Err(Error::Protection {
+                    block: 0,
+                    field: PiField::ReferenceTag,
+                    ..
+                })
+            ));
+        }
+        let shifted = Protection::check_all(lba + 1, 0x1234);
+        assert!(matches!(
+            ns.verify_pi(&data, metadata.as_deref(), &shifted),
+            Err(Error::Protection {
+                block: 0,
+                field: PiField::ReferenceTag,
+                ..
+            })
+        ));
+
+        data[ns.block_size as usize + 7] ^= 1;
+        assert!(matches!(
+            ns.verify_pi(&data, metadata.as_deref(), &protection),
+            Err(Error::Protection {
+                block: 1,
+                field: PiField::Guard,
+                ..
+            })
+        ));
+    }
+}
+
+#[test]
+pub fn protected_read_write() {
+    let pci_addr = &get_pci_addr();
+
+    let lba = 0;
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let Some(format) = ns.protection else {
+        eprintln!("Skipping, namespace 1 is not formatted with protection information");
+        return;
+    };
+
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let blocks = (PAGESIZE_4KIB as u64 / ns.data_size()).max(1);
+    let bytes = (blocks * ns.block_size) as usize;
+    let metadata_bytes = (blocks * ns.metadata_size) as usize;
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, 2 * PAGESIZE_4KIB.max(bytes));
+    let mut metadata: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+
+    let rand_block = &(0..bytes).map(|_| rand::random::<u8>()).collect::<Vec<_>>()[..];
+    buffer[..bytes].copy_from_slice(rand_block);
+
+    let protection = Protection::check_all(lba, 0x1234);
+    ns.generate_pi(
+        &mut buffer[..bytes],
+        (!ns.extended_lba).then(|| &mut metadata[0..metadata_bytes]),
+        &protection,
+    )
+    .unwrap();
+    let written = buffer[..bytes].to_vec();
+    let metadata = metadata.slice(0..metadata_bytes);
+    let separate = (!ns.extended_lba).then_some(&metadata);
+
+    qpair
+        .submit_io_pi(
+            &ns,
+            &buffer.slice(0..bytes),
+            separate,
+            lba,
+            true,
+            protection,
+        )
+        .unwrap();
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    buffer[..bytes].fill(0);
+    qpair
+        .submit_io_pi(
+            &ns,
+            &buffer.slice(0..bytes),
+            separate,
+            lba,
+            false,
+            protection,
+        )
+        .unwrap();
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    assert_eq!(&written[..], &buffer[..bytes]);
+    ns.verify_pi(
+        &buffer[..bytes],
+        separate.map(|metadata| &metadata[0..metadata_bytes]),
+        &protection,
+    )
+    .unwrap();
+
+    // the controller rejects blocks whose reference tag doesn't match their LBA
+    if format.pi_type == PiType::Type1 {
+        let wrong = Protection::check_all(lba + 1, 0x1234);
+        qpair
+            .submit_io_pi(&ns, &buffer.slice(0..bytes), separate, lba, true, wrong)
+            .unwrap();
+        assert!(qpair.complete_io(1).unwrap()[0].result().is_err());
+    }
+
+    // plain I/O has the controller insert and check the protection information, if that is all the metadata
+    if ns.check_metadata().is_ok() && !ns.extended_lba {
+        qpair
+            .submit_io(&ns, &buffer.slice(0..bytes), lba, true)
+            .unwrap();
+        qpair.complete_io(1).unwrap()[0].result().unwrap();
+        buffer[..bytes].fill(0);
+        qpair
+            .submit_io(&ns, &buffer.slice(0..bytes), lba, false)
+            .unwrap();
+        qpair.complete_io(1).unwrap()[0].result().unwrap();
+        assert_eq!(&written[..], &buffer[..bytes]);
+    }
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
@@ -0,0 +1,52 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::PAGESIZE_2MIB;
+
+mod common;
+use common::*;
+
+#[test]
+pub fn prp_list_read_write() {
+    let pci_addr = &get_pci_addr();
+
+    let lba = 0;
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, 2 * PAGESIZE_2MIB);
+
+    // 1 MiB transfer starting at an offset into the first page
+    let offset = 512;
+    let bytes = 1024 * 1024;
+    let rand_block = &(0..bytes).map(|_| rand::random::<u8>()).collect::<Vec<_>>()[..];
+    buffer[offset..offset + bytes].copy_from_slice(rand_block);
+
+    let request = qpair
+        .submit_io(&ns, &buffer.slice(offset..offset + bytes), lba, true)
+        .expect("queue full");
+    let completed = qpair.complete_io(1).unwrap();
+    assert_eq!(completed[0].request, request);
+    assert!(completed[0].is_success(), "IO Completion failed!");
+
+    buffer[offset..offset + bytes].fill(0);
+
+    let request = qpair
+        .submit_io(&ns, &buffer.slice(offset..offset + bytes), lba, false)
+        .expect("queue full");
+    let completed = qpair.complete_io(1).unwrap();
+    assert_eq!(completed[0].request, request);
+    assert!(completed[0].is_success(), "IO Completion failed!");
+
+    assert_eq!(
+        rand_block,
+        &buffer[offset..offset + bytes],
+        "Data read from NVMe does not match expected data"
+    );
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
@@ -3,8 +3,8 @@ use std::error::Error;
 use std::process;
 use std::time::{Duration, Instant};
 use vroom::memory::{Dma, DmaSlice};
-use vroom::PAGESIZE_2MIB;
-use vroom::{NvmeDevice, QUEUE_LENGTH};
+use vroom::NvmeDevice;
+use vroom::{PAGESIZE_2MIB, PAGESIZE_4KIB};
 
 mod common;
 use common::*;
@@ -42,9 +42,11 @@ fn qd1(
 ) -> Result<NvmeDevice, Box<dyn Error>> {
     let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_2MIB);
 
-    let blocks = 8;
-    let bytes = 512 * blocks;
-    let ns_blocks = nvme.namespaces.get(&1).unwrap().blocks / blocks - 1; // - blocks - 1;
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    // 4 KiB per I/O
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+    let bytes = blocks * ns.block_size;
+    let ns_blocks = ns.blocks / blocks - 1; // - blocks - 1;
 
     let mut rng = thread_rng();
 
@@ -63,23 +65,15 @@ fn qd1(
 
         let before = Instant::now();
         if write {
-            nvme_write(&mut nvme, &buffer.slice(0..bytes as usize), lba * blocks);
-            nvme_read(
-                &mut nvme,
-                &mut buffer.slice(0..bytes as usize),
-                lba * blocks,
-            );
+            nvme.write(&ns, &buffer.slice(0..bytes as usize), lba * blocks)?;
+            nvme.read(&ns, &buffer.slice(0..bytes as usize), lba * blocks)?;
             let read_buf = &buffer[0..rand_block.len()];
             assert_eq!(
                 rand_block, read_buf,
                 "Data read from NVMe does not match expected data"
             );
         } else {
-            nvme_read(
-                &mut nvme,
-                &mut buffer.slice(0..bytes as usize),
-                lba * blocks,
-            );
+            nvme.read(&ns, &buffer.slice(0..bytes as usize), lba * blocks)?;
         }
 
         let elapsed = before.elapsed();
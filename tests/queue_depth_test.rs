@@ -5,8 +5,8 @@ use std::error::Error;
 use std::process;
 use std::time::{Duration, Instant};
 use vroom::memory::{Dma, DmaSlice};
-use vroom::PAGESIZE_2MIB;
-use vroom::{NvmeDevice, QUEUE_LENGTH};
+use vroom::NvmeDevice;
+use vroom::{PAGESIZE_2MIB, PAGESIZE_4KIB};
 
 #[test]
 pub fn qd_n_test() {
@@ -38,21 +38,25 @@ fn qd_n(
     duration: Duration,
     write: bool,
 ) -> Result<NvmeDevice, Box<dyn Error>> {
-    let blocks = 8;
-    let ns_blocks = nvme.namespaces.get(&1).unwrap().blocks / blocks;
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    // 4 KiB per I/O
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+    let ns_blocks = ns.blocks / blocks;
 
     let range = (0, ns_blocks);
 
     let mut rng = rand::thread_rng();
-    let bytes = 512 * blocks as usize;
+    let bytes = (blocks * ns.block_size) as usize;
     let mut total = std::time::Duration::ZERO;
 
     let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_2MIB);
 
-    let mut qpair = nvme.create_io_queue_pair(QUEUE_LENGTH).unwrap_or_else(|e| {
-        eprintln!("Creation of IO Queue Pair failed: {}", e);
-        process::exit(1);
-    });
+    let mut qpair = nvme
+        .create_io_queue_pair(queue_depth + 1)
+        .unwrap_or_else(|e| {
+            eprintln!("Creation of IO Queue Pair failed: {}", e);
+            process::exit(1);
+        });
 
     let bytes_mult = queue_depth;
     let rand_block = &(0..(bytes_mult * bytes))
@@ -76,27 +80,30 @@ fn qd_n(
         }
         if outstanding_ops == queue_depth {
             let io_result = qpair.complete_io(1);
-            if io_result.is_none() {
+            if io_result.map_or(true, |completed| completed.iter().any(|c| !c.is_success())) {
                 eprintln!("IO Completion failed!");
                 process::exit(1);
             }
             outstanding_ops -= 1;
         }
-        qpair.submit_io(
-            &buffer.slice((outstanding_ops * bytes)..(outstanding_ops + 1) * bytes),
-            lba * blocks,
-            write,
-        );
+        qpair
+            .submit_io(
+                &ns,
+                &buffer.slice((outstanding_ops * bytes)..(outstanding_ops + 1) * bytes),
+                lba * blocks,
+                write,
+            )
+            .unwrap();
         total += before.elapsed();
         outstanding_ops += 1;
     }
 
     if outstanding_ops != 0 {
         let before = Instant::now();
-        qpair.complete_io(outstanding_ops);
+        qpair.complete_io(outstanding_ops).unwrap();
         total += before.elapsed();
     }
-    assert!(qpair.sub_queue.is_empty());
+    assert!(qpair.sub_queue().is_empty());
     nvme.delete_io_queue_pair(&qpair).unwrap_or_else(|e| {
         eprintln!("Deletion of io queue pair failed: {}", e);
         process::exit(1);
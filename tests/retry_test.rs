@@ -0,0 +1,93 @@
+use std::time::Duration;
+use vroom::{NvmeStatus, RetryPolicy};
+
+// generic status Namespace Not Ready, which is retryable
+const NAMESPACE_NOT_READY: u16 = 0x82;
+// generic status Invalid Field in Command, which is not
+const INVALID_FIELD: u16 = 0x02;
+const DNR: u16 = 1 << 14;
+
+const fn with_crd(status: u16, crd: u16) -> u16 {
+    status | (crd << 11)
+}
+
+const CRDT: [u16; 3] = [1, 5, 20];
+
+fn policy() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 4,
+        backoff: Duration::from_micros(100),
+        respect_crd: true,
+    }
+}
+
+#[test]
+pub fn backoff_doubles() {
+    let status = NvmeStatus::new(NAMESPACE_NOT_READY);
+    let delays: Vec<_> = (1..4)
+        .map(|attempts| policy().retry_delay(status, attempts, CRDT))
+        .collect();
+    assert_eq!(
+        delays,
+        [
+            Some(Duration::from_micros(100)),
+            Some(Duration::from_micros(200)),
+            Some(Duration::from_micros(400)),
+        ]
+    );
+}
+
+#[test]
+pub fn max_attempts_exhausted() {
+    let status = NvmeStatus::new(NAMESPACE_NOT_READY);
+    assert!(policy().retry_delay(status, 3, CRDT).is_some());
+    assert_eq!(policy().retry_delay(status, 4, CRDT), None);
+    assert_eq!(policy().retry_delay(status, 5, CRDT), None);
+    assert_eq!(RetryPolicy::NONE.retry_delay(status, 1, CRDT), None);
+}
+
+#[test]
+pub fn command_retry_delay_selects_crdt() {
+    for (crd, millis) in [(1, 100), (2, 500), (3, 2000)] {
+        let status = NvmeStatus::new(with_crd(NAMESPACE_NOT_READY, crd));
+        assert_eq!(status.crd, crd as u8);
+        assert_eq!(
+            policy().retry_delay(status, 1, CRDT),
+            Some(Duration::from_millis(millis))
+        );
+    }
+
+    // the backoff applies once it exceeds the delay selected by the controller
+    let status = NvmeStatus::new(with_crd(NAMESPACE_NOT_READY, 1));
+    let policy = RetryPolicy {
+        backoff: Duration::from_millis(80),
+        ..policy()
+    };
+    assert_eq!(
+        policy.retry_delay(status, 1, CRDT),
+        Some(Duration::from_millis(100))
+    );
+    assert_eq!(
+        policy.retry_delay(status, 2, CRDT),
+        Some(Duration::from_millis(160))
+    );
+
+    let ignore_crd = RetryPolicy {
+        respect_crd: false,
+        ..policy
+    };
+    assert_eq!(
+        ignore_crd.retry_delay(status, 1, CRDT),
+        Some(Duration::from_millis(80))
+    );
+}
+
+#[test]
+pub fn not_retryable() {
+    let dnr = NvmeStatus::new(NAMESPACE_NOT_READY | DNR);
+    assert!(dnr.dnr);
+    assert_eq!(policy().retry_delay(dnr, 1, CRDT), None);
+
+    let invalid = NvmeStatus::new(INVALID_FIELD);
+    assert_eq!(policy().retry_delay(invalid, 1, CRDT), None);
+}
@@ -0,0 +1,91 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::PAGESIZE_4KIB;
+
+mod common;
+use common::*;
+
+#[test]
+pub fn sgl_read_write() {
+    let pci_addr = &get_pci_addr();
+
+    let lba = 0;
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, 8 * PAGESIZE_4KIB);
+
+    // three pages with gaps in between, so the transfer is not physically contiguous
+    let offsets = [0, 2 * PAGESIZE_4KIB, 5 * PAGESIZE_4KIB];
+    let bytes = offsets.len() * PAGESIZE_4KIB;
+    let rand_block = (0..bytes).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+    for (i, &offset) in offsets.iter().enumerate() {
+        buffer[offset..offset + PAGESIZE_4KIB]
+            .copy_from_slice(&rand_block[i * PAGESIZE_4KIB..(i + 1) * PAGESIZE_4KIB]);
+    }
+    let segments = offsets
+        .iter()
+        .map(|&offset| buffer.slice(offset..offset + PAGESIZE_4KIB))
+        .collect::<Vec<_>>();
+
+    let request = match qpair.submit_io_sgl(&ns, &segments, lba, true) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("Skipping, SGLs not supported: {}", e);
+            nvme.delete_io_queue_pair(&qpair).unwrap();
+            return;
+        }
+    };
+    let completed = qpair.complete_io(1).unwrap();
+    assert_eq!(completed[0].request, request);
+    assert!(completed[0].is_success(), "IO Completion failed!");
+
+    buffer[..8 * PAGESIZE_4KIB].fill(0);
+
+    let request = qpair
+        .submit_io_sgl(&ns, &segments, lba, false)
+        .expect("queue full");
+    let completed = qpair.complete_io(1).unwrap();
+    assert_eq!(completed[0].request, request);
+    assert!(completed[0].is_success(), "IO Completion failed!");
+
+    for (i, &offset) in offsets.iter().enumerate() {
+        assert_eq!(
+            &rand_block[i * PAGESIZE_4KIB..(i + 1) * PAGESIZE_4KIB],
+            &buffer[offset..offset + PAGESIZE_4KIB],
+            "Data read from NVMe does not match expected data"
+        );
+    }
+
+    // a read ending in the middle of a block discards the rest of it into a bit bucket
+    buffer[..8 * PAGESIZE_4KIB].fill(0);
+    let short = PAGESIZE_4KIB - 256;
+    let mut segments = segments;
+    segments[2] = buffer.slice(offsets[2]..offsets[2] + short);
+    match qpair.submit_io_sgl(&ns, &segments, lba, false) {
+        Ok(request) => {
+            let completed = qpair.complete_io(1).unwrap();
+            assert_eq!(completed[0].request, request);
+            assert!(completed[0].is_success(), "IO Completion failed!");
+            assert_eq!(
+                &rand_block[2 * PAGESIZE_4KIB..2 * PAGESIZE_4KIB + short],
+                &buffer[offsets[2]..offsets[2] + short],
+                "Data read from NVMe does not match expected data"
+            );
+            assert!(
+                buffer[offsets[2] + short..offsets[2] + PAGESIZE_4KIB]
+                    .iter()
+                    .all(|&b| b == 0),
+                "Discarded data was written to the buffer"
+            );
+        }
+        Err(e) => eprintln!("Skipping, bit buckets not supported: {}", e),
+    }
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
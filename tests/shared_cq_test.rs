@@ -0,0 +1,68 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{QueuePriority, PAGESIZE_4KIB};
+
+mod common;
+use common::*;
+
+#[test]
+pub fn shared_completion_queue() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+    let sq_id = nvme
+        .attach_io_submission_queue(&mut qpair, 64, QueuePriority::Medium)
+        .unwrap_or_else(|e| {
+            eprintln!("Creation of second Submission Queue failed: {}", e);
+            std::process::exit(1);
+        });
+    assert_eq!(qpair.sub_queue_ids(), vec![qpair.id, sq_id]);
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, 2 * PAGESIZE_4KIB);
+    let rand_block = &(0..2 * PAGESIZE_4KIB)
+        .map(|_| rand::random::<u8>())
+        .collect::<Vec<_>>()[..];
+    buffer[..rand_block.len()].copy_from_slice(rand_block);
+
+    // one block through each submission queue, both complete on the same completion queue
+    let first = qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, true)
+        .expect("queue full");
+    let second = qpair
+        .submit_io_on(
+            sq_id,
+            &ns,
+            &buffer.slice(PAGESIZE_4KIB..2 * PAGESIZE_4KIB),
+            8,
+            true,
+        )
+        .expect("queue full");
+    assert_eq!(second.sq_id(), sq_id);
+
+    let completed = qpair.complete_io(2).unwrap();
+    assert_eq!(completed.len(), 2);
+    for c in &completed {
+        assert!(c.is_success(), "IO Completion failed!");
+        let expected = if c.sq_id == sq_id { second } else { first };
+        assert_eq!(c.request, expected);
+    }
+
+    buffer[..rand_block.len()].fill(0);
+    qpair
+        .submit_io_on(sq_id, &ns, &buffer.slice(0..2 * PAGESIZE_4KIB), 0, false)
+        .expect("queue full");
+    let completed = qpair.complete_io(1).unwrap();
+    assert!(completed[0].is_success(), "IO Completion failed!");
+
+    assert_eq!(
+        rand_block,
+        &buffer[..rand_block.len()],
+        "Data read from NVMe does not match expected data"
+    );
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+}
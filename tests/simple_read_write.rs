@@ -1,5 +1,5 @@
-use vroom::memory::Dma;
-use vroom::PAGESIZE_2MIB;
+use vroom::memory::{Dma, DmaSlice};
+use vroom::{PAGESIZE_2MIB, PAGESIZE_4KIB};
 
 mod common;
 use common::*;
@@ -11,15 +11,22 @@ pub fn simple_read_write() {
     let lba = 0;
 
     let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
 
     let bytes: &[u8] = b"hello world! vroom test bytes";
     let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_2MIB);
 
     buffer[..bytes.len()].copy_from_slice(bytes);
-    nvme_write(&mut nvme, &buffer, lba);
+    nvme.write(&ns, &buffer, lba).unwrap_or_else(|e| {
+        eprintln!("NVMe write failed: {}", e);
+        std::process::exit(1);
+    });
 
     buffer[..bytes.len()].fill_with(Default::default);
-    nvme_read(&mut nvme, &mut buffer, lba);
+    nvme.read(&ns, &buffer, lba).unwrap_or_else(|e| {
+        eprintln!("NVMe read failed: {}", e);
+        std::process::exit(1);
+    });
 
     let read_buf = &buffer[0..bytes.len()];
     assert_eq!(
@@ -27,3 +34,38 @@ pub fn simple_read_write() {
         "Data read from NVMe does not match expected data"
     );
 }
+
+#[test]
+pub fn unaligned_read_write() {
+    let pci_addr = &get_pci_addr();
+
+    let lba = 0;
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+
+    // starts in the middle of a page, so 8 KiB of it would span three pages
+    let offset = 512;
+    let bytes = 4 * PAGESIZE_4KIB;
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, 2 * PAGESIZE_2MIB);
+    let rand_block = &(0..bytes).map(|_| rand::random::<u8>()).collect::<Vec<_>>()[..];
+    buffer[offset..offset + bytes].copy_from_slice(rand_block);
+
+    nvme.write(&ns, &buffer.slice(offset..offset + bytes), lba)
+        .unwrap();
+    buffer[..offset + bytes].fill(0);
+    // read into another offset, nothing before it may be touched
+    let offset = 1024;
+    nvme.read(&ns, &buffer.slice(offset..offset + bytes), lba)
+        .unwrap();
+
+    assert_eq!(
+        rand_block,
+        &buffer[offset..offset + bytes],
+        "Data read from NVMe does not match expected data"
+    );
+    assert!(
+        buffer[..offset].iter().all(|&b| b == 0),
+        "Data was read into the wrong pages"
+    );
+}
@@ -0,0 +1,33 @@
+use vroom::status::{GenericStatus, MediaErrorStatus, StatusCode, StatusCodeType};
+use vroom::NvmeStatus;
+
+#[test]
+pub fn status_decoding() {
+    let success = NvmeStatus::new(0);
+    assert!(success.is_success());
+    assert_eq!(success, NvmeStatus::SUCCESS);
+
+    // LBA out of range, do not retry
+    let status = NvmeStatus::new(1 << 14 | 0x80);
+    assert!(!status.is_success());
+    assert_eq!(status.sct, StatusCodeType::Generic);
+    assert_eq!(status.sc, StatusCode::Generic(GenericStatus::LbaOutOfRange));
+    assert!(status.dnr);
+
+    // unrecovered read error, more information in the error log, retry after CRDT2
+    let status = NvmeStatus::new(1 << 13 | 2 << 11 | 2 << 8 | 0x81);
+    assert_eq!(status.sct, StatusCodeType::MediaError);
+    assert_eq!(
+        status.sc,
+        StatusCode::MediaError(MediaErrorStatus::UnrecoveredReadError)
+    );
+    assert_eq!(status.crd, 2);
+    assert!(status.more);
+    assert!(!status.dnr);
+
+    let status = NvmeStatus::new(2 << 8 | 0x7F);
+    assert_eq!(
+        status.sc,
+        StatusCode::MediaError(MediaErrorStatus::Other(0x7F))
+    );
+}
@@ -0,0 +1,42 @@
+use vroom::memory::{Dma, DmaSlice};
+use vroom::PAGESIZE_4KIB;
+
+mod common;
+use common::*;
+
+#[test]
+pub fn write_zeroes() {
+    let pci_addr = &get_pci_addr();
+
+    let mut nvme = init_nvme(pci_addr);
+    let ns = *nvme.namespaces.get(&1).unwrap();
+    let mut qpair = nvme.create_io_queue_pair(64).unwrap_or_else(|e| {
+        eprintln!("Creation of IO Queue Pair failed: {}", e);
+        std::process::exit(1);
+    });
+
+    let mut buffer: Dma<u8> = allocate_dma_buffer(&nvme, PAGESIZE_4KIB);
+    buffer[..PAGESIZE_4KIB].fill(0xFF);
+    let blocks = PAGESIZE_4KIB as u64 / ns.block_size;
+
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, true)
+        .expect("queue full");
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    qpair.write_zeroes(&ns, 0, blocks, false).unwrap();
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+
+    qpair
+        .submit_io(&ns, &buffer.slice(0..PAGESIZE_4KIB), 0, false)
+        .expect("queue full");
+    qpair.complete_io(1).unwrap()[0].result().unwrap();
+    assert!(
+        buffer[..PAGESIZE_4KIB].iter().all(|&b| b == 0),
+        "Blocks were not zeroed"
+    );
+
+    nvme.delete_io_queue_pair(&qpair).unwrap();
+
+    nvme.write_zeroes(&ns, blocks, blocks, true).unwrap();
+}